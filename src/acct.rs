@@ -0,0 +1,328 @@
+use crate::{taskstats, AsBuf, Cpu, Memory, TaskStats};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::mem;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Size of `struct acct_v3`
+pub const ACCT_V3_SIZE: usize = 64;
+
+/// Version of `struct acct_v3` in `ac_version`
+const ACCT_VERSION: u8 = 3;
+
+/// Bit of `ac_version` set by big endian kernels
+const ACCT_BYTEORDER: u8 = 0x80;
+
+/// Clock ticks per second of times in records, which is fixed regardless of
+/// the kernel's HZ
+const AHZ: u64 = 100;
+
+/// Flag of a task which forked but didn't exec
+pub const AFORK: u8 = 0x01;
+/// Flag of a task which used super-user privileges
+pub const ASU: u8 = 0x02;
+/// Flag of a task which dumped core
+pub const ACORE: u8 = 0x08;
+/// Flag of a task which was killed by a signal
+pub const AXSIG: u8 = 0x10;
+
+/// A record of BSD-style process accounting written by the kernel's `acct(2)`,
+/// in the format of `struct acct_v3`.
+///
+/// Process accounting records no delays, I/O in bytes or context switches.
+/// The delay fields of `TaskStats` converted by `From<&AcctRecord>` are
+/// zero, so reports over both pacct files and taskstats exits have to tell
+/// them apart by their source.
+#[derive(Debug, Clone)]
+pub struct AcctRecord {
+    /// `AFORK`, `ASU`, `ACORE` and `AXSIG`
+    pub flags: u8,
+    /// Device number of the controlling terminal
+    pub tty: u16,
+    /// Exit status as returned by `wait(2)`
+    pub exit_code: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: u32,
+    pub ppid: u32,
+    /// Time when the task started
+    pub begin_time: SystemTime,
+    pub elapsed: Duration,
+    /// Command name, up to 15 characters
+    pub comm: String,
+    /// User and system CPU time, with running times zero
+    pub cpu: Cpu,
+    /// Page faults, with virtual memory usage integrated over the CPU time of
+    /// the task approximated out of `virtual_memory_kb`, and RSS usage zero
+    pub memory: Memory,
+    /// Size of the virtual memory of the process in KiB at its exit, zero
+    /// unless the last thread of the process
+    pub virtual_memory_kb: u64,
+    /// Characters transferred by read and write syscalls
+    pub io_chars: u64,
+    /// Blocks read or written, which Linux doesn't count
+    pub rw_blocks: u64,
+    /// Number of swaps, which Linux doesn't count
+    pub swaps: u64,
+}
+
+impl AcctRecord {
+    /// Parse a record of `ACCT_V3_SIZE` bytes in the byte order of the
+    /// kernel which wrote it.
+    pub fn parse(buf: &[u8]) -> io::Result<Self> {
+        if buf.len() < ACCT_V3_SIZE {
+            return Err(invalid_data(format!(
+                "short acct record: {} bytes",
+                buf.len()
+            )));
+        }
+        let version = buf[1];
+        if version & !ACCT_BYTEORDER != ACCT_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported acct version: {}",
+                version & !ACCT_BYTEORDER
+            )));
+        }
+        let big_endian = version & ACCT_BYTEORDER != 0;
+        let u16_at = |i: usize| {
+            let bytes = buf[i..i + 2].try_into().unwrap();
+            if big_endian {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            }
+        };
+        let u32_at = |i: usize| {
+            let bytes = buf[i..i + 4].try_into().unwrap();
+            if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        };
+        let comp_at = |i: usize| decode_comp(u16_at(i));
+        let ticks = |t: u64| Duration::from_micros(t * 1_000_000 / AHZ);
+
+        let etime = f32::from_bits(u32_at(28)).max(0.0) as f64;
+        let elapsed_us = (etime * 1_000_000.0 / AHZ as f64).round() as u64;
+        let utime = ticks(comp_at(32));
+        let stime = ticks(comp_at(34));
+        let virtual_memory_kb = comp_at(36);
+        let comm = &buf[48..64];
+        let comm_len = comm.iter().position(|b| *b == 0).unwrap_or(comm.len());
+        Ok(Self {
+            flags: buf[0],
+            tty: u16_at(2),
+            exit_code: u32_at(4),
+            uid: u32_at(8),
+            gid: u32_at(12),
+            pid: u32_at(16),
+            ppid: u32_at(20),
+            begin_time: UNIX_EPOCH + Duration::from_secs(u32_at(24) as u64),
+            elapsed: Duration::from_micros(elapsed_us),
+            comm: String::from_utf8_lossy(&comm[..comm_len]).into_owned(),
+            cpu: Cpu {
+                utime_total: utime,
+                stime_total: stime,
+                real_time_total: Duration::ZERO,
+                virtual_time_total: Duration::ZERO,
+            },
+            memory: Memory {
+                rss_total: 0,
+                // MB-usecs as taskstats reports them, where the "MB" is a MiB
+                virt_total: virtual_memory_kb.saturating_mul((utime + stime).as_micros() as u64)
+                    / 1024,
+                minor_faults: comp_at(42),
+                major_faults: comp_at(44),
+            },
+            virtual_memory_kb,
+            io_chars: comp_at(38),
+            rw_blocks: comp_at(40),
+            swaps: comp_at(46),
+        })
+    }
+}
+
+impl From<&AcctRecord> for TaskStats {
+    fn from(record: &AcctRecord) -> Self {
+        let mut raw: taskstats = unsafe { mem::zeroed() };
+        raw.ac_flag = record.flags;
+        raw.ac_exitcode = record.exit_code;
+        raw.ac_uid = record.uid;
+        raw.ac_gid = record.gid;
+        raw.ac_pid = record.pid;
+        raw.ac_ppid = record.ppid;
+        raw.ac_btime = record
+            .begin_time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32;
+        raw.ac_etime = record.elapsed.as_micros() as u64;
+        raw.ac_utime = record.cpu.utime_total.as_micros() as u64;
+        raw.ac_stime = record.cpu.stime_total.as_micros() as u64;
+        raw.ac_minflt = record.memory.minor_faults;
+        raw.ac_majflt = record.memory.major_faults;
+        raw.virtmem = record.memory.virt_total;
+        for (dst, src) in raw.ac_comm.iter_mut().zip(record.comm.bytes()) {
+            *dst = src as _;
+        }
+        TaskStats::from(raw.as_buf())
+    }
+}
+
+/// Reads records out of a process accounting file, e.g. /var/log/account/pacct.
+///
+/// Reading stops at a record partially written at the end of the file.
+pub struct AcctReader<R: Read> {
+    reader: R,
+}
+
+impl AcctReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> AcctReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl<R: Read> Iterator for AcctReader<R> {
+    type Item = io::Result<AcctRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0; ACCT_V3_SIZE];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => Some(AcctRecord::parse(&buf)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Decode `comp_t`, a 13-bit fraction with a 3-bit base 8 exponent
+fn decode_comp(c: u16) -> u64 {
+    ((c & 0x1fff) as u64) << ((c >> 13) * 3)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACCT_V3: &[u8] = include_bytes!("../tests/fixtures/pacct-v3");
+    const PACCT_V3_BE: &[u8] = include_bytes!("../tests/fixtures/pacct-v3-be");
+
+    fn read(buf: &[u8]) -> Vec<AcctRecord> {
+        AcctReader::new(buf).map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_decode_comp() {
+        assert_eq!(1000, decode_comp(1000));
+        assert_eq!(8000, decode_comp((1 << 13) | 1000));
+        assert_eq!(0x1fff << 21, decode_comp(0xffff));
+    }
+
+    #[test]
+    fn test_read_kernel_records() {
+        let records = read(PACCT_V3);
+        let comms: Vec<_> = records.iter().map(|r| r.comm.as_str()).collect();
+        assert_eq!(vec!["true", "sleep", "sh", "dd", "python3"], comms);
+
+        let sleep = &records[1];
+        assert_eq!(Duration::from_millis(200), sleep.elapsed);
+        assert_eq!(records[0].pid + 1, sleep.pid);
+        assert_eq!(records[4].pid, sleep.ppid);
+        assert_eq!(0, sleep.flags);
+
+        let sh = &records[2];
+        assert_eq!(3 << 8, sh.exit_code);
+
+        let dd = &records[3];
+        assert_eq!(3956, dd.virtual_memory_kb);
+        assert_eq!(326, dd.memory.minor_faults);
+        assert_eq!(1, dd.memory.major_faults);
+
+        let python = &records[4];
+        assert_eq!(0, python.flags & AFORK);
+        assert_eq!(Duration::from_millis(360), python.elapsed);
+    }
+
+    #[test]
+    fn test_read_big_endian() {
+        let records = read(PACCT_V3_BE);
+        assert_eq!(1, records.len());
+        let make = &records[0];
+        assert_eq!(AFORK | ASU, make.flags);
+        assert_eq!(0x8801, make.tty);
+        assert_eq!(
+            (1000, 100, 4242, 1),
+            (make.uid, make.gid, make.pid, make.ppid)
+        );
+        assert_eq!(
+            UNIX_EPOCH + Duration::from_secs(1_680_000_000),
+            make.begin_time
+        );
+        assert_eq!(Duration::from_millis(1500), make.elapsed);
+        assert_eq!(Duration::from_secs(80), make.cpu.utime_total);
+        assert_eq!(Duration::from_millis(500), make.cpu.stime_total);
+        assert_eq!(2048, make.virtual_memory_kb);
+        assert_eq!(2048 * 80_500_000 / 1024, make.memory.virt_total);
+        assert_eq!(0, make.memory.rss_total);
+        assert_eq!(4096, make.io_chars);
+        assert_eq!(
+            (300, 2),
+            (make.memory.minor_faults, make.memory.major_faults)
+        );
+        assert_eq!("make", make.comm);
+    }
+
+    #[test]
+    fn test_into_task_stats() {
+        let make = &read(PACCT_V3_BE)[0];
+        let ts = TaskStats::from(make);
+        assert_eq!(4242, ts.tid);
+        assert_eq!("make", ts.comm());
+        assert_eq!(make.elapsed, ts.elapsed());
+        assert_eq!(make.cpu.utime_total, ts.cpu.utime_total);
+        assert_eq!(make.memory.virt_total, ts.memory.virt_total);
+        assert_eq!(
+            (1000, 100, 1),
+            (ts.inner().ac_uid, ts.inner().ac_gid, ts.inner().ac_ppid)
+        );
+        assert_eq!(1_680_000_000, ts.inner().ac_btime);
+        assert_eq!(0, ts.delays.cpu.count);
+        assert!(ts.delays.blkio.delay_total.is_zero());
+    }
+
+    #[test]
+    fn test_virt_total_saturates() {
+        let mut buf = PACCT_V3_BE.to_vec();
+        // The largest comp_t of CPU time and memory
+        buf[32..38].copy_from_slice(&[0xff; 6]);
+        let record = AcctRecord::parse(&buf).unwrap();
+        assert_eq!(u64::MAX / 1024, record.memory.virt_total);
+    }
+
+    #[test]
+    fn test_partial_and_invalid_records() {
+        let mut buf = PACCT_V3_BE.to_vec();
+        buf.extend_from_slice(&PACCT_V3_BE[..30]);
+        assert_eq!(1, read(&buf).len());
+
+        let mut v2 = PACCT_V3_BE.to_vec();
+        v2[1] = 2;
+        let mut reader = AcctReader::new(&v2[..]);
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,340 @@
+use crate::TaskStats;
+use std::collections::btree_map::{self, BTreeMap};
+use std::fmt;
+
+/// Key to group tasks by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Comm,
+    Uid,
+    Gid,
+    /// The process of a task. `GroupBy::key_of` takes it from `ac_tgid`, so it
+    /// only has to be given along with the stats for records of taskstats older
+    /// than version 12, which don't tell it
+    Tgid,
+    Ppid,
+}
+
+impl GroupBy {
+    /// Find a key by its name: `comm`, `uid`, `gid`, `tgid` or `ppid`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "comm" => Some(GroupBy::Comm),
+            "uid" => Some(GroupBy::Uid),
+            "gid" => Some(GroupBy::Gid),
+            "tgid" => Some(GroupBy::Tgid),
+            "ppid" => Some(GroupBy::Ppid),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GroupBy::Comm => "comm",
+            GroupBy::Uid => "uid",
+            GroupBy::Gid => "gid",
+            GroupBy::Tgid => "tgid",
+            GroupBy::Ppid => "ppid",
+        }
+    }
+
+    /// Take the key out of stats of a task belonging to the process `tgid`.
+    pub fn key(&self, tgid: u32, ts: &TaskStats) -> Key {
+        match self {
+            GroupBy::Comm => Key::Name(ts.comm()),
+            GroupBy::Uid => Key::Id(ts.inner().ac_uid),
+            GroupBy::Gid => Key::Id(ts.inner().ac_gid),
+            GroupBy::Tgid => Key::Id(tgid),
+            GroupBy::Ppid => Key::Id(ts.inner().ac_ppid),
+        }
+    }
+
+    /// Take the key out of stats of a task, taking its process from `ac_tgid`.
+    ///
+    /// A task of records lacking `ac_tgid` is taken as a process by itself.
+    pub fn key_of(&self, ts: &TaskStats) -> Key {
+        self.key(ts.tgid().unwrap_or(ts.tid), ts)
+    }
+}
+
+/// Value of a key taken by `GroupBy::key`, ordered numerically for IDs.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Key {
+    Id(u32),
+    Name(String),
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::Id(id) => id.fmt(f),
+            Key::Name(name) => name.fmt(f),
+        }
+    }
+}
+
+/// Stats of tasks summed up.
+///
+/// Counters, delay counts and delay totals are summed, so that averages of
+/// delays (`DelayStat::average`) are of all delays of the tasks rather than
+/// averages of averages. Elapsed times are also summed, so that delays in
+/// percentage (`DelayStat::percent_of(stats.elapsed())`) are of the total
+/// lifetime of the tasks. High-watermarks take the largest one and the other
+/// fields, e.g. tid and comm, are of the first task.
+#[derive(Debug, Clone, Copy)]
+pub struct Group {
+    /// Number of tasks summed up
+    pub tasks: usize,
+    pub stats: TaskStats,
+}
+
+impl Group {
+    pub fn new(ts: &TaskStats) -> Self {
+        Self {
+            tasks: 1,
+            stats: *ts,
+        }
+    }
+
+    /// Add a task to this group.
+    pub fn add(&mut self, ts: &TaskStats) {
+        self.merge(&Group::new(ts));
+    }
+
+    /// Add all tasks of `other` to this group.
+    pub fn merge(&mut self, other: &Group) {
+        self.tasks += other.tasks;
+        self.stats += other.stats;
+    }
+}
+
+/// Groups of tasks keyed by `K`, in the order of keys.
+#[derive(Debug, Clone)]
+pub struct Aggregate<K: Ord> {
+    groups: BTreeMap<K, Group>,
+}
+
+impl<K: Ord> Default for Aggregate<K> {
+    fn default() -> Self {
+        Self {
+            groups: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord> Aggregate<K> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a task to the group of `key`.
+    pub fn add(&mut self, key: K, ts: &TaskStats) {
+        self.merge(key, &Group::new(ts));
+    }
+
+    /// Add all tasks of `group` to the group of `key`.
+    pub fn merge(&mut self, key: K, group: &Group) {
+        match self.groups.entry(key) {
+            btree_map::Entry::Occupied(mut e) => e.get_mut().merge(group),
+            btree_map::Entry::Vacant(e) => {
+                e.insert(*group);
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&Group> {
+        self.groups.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, K, Group> {
+        self.groups.iter()
+    }
+}
+
+impl<K: Ord> IntoIterator for Aggregate<K> {
+    type Item = (K, Group);
+    type IntoIter = btree_map::IntoIter<K, Group>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.groups.into_iter()
+    }
+}
+
+impl<'a, K: Ord> IntoIterator for &'a Aggregate<K> {
+    type Item = (&'a K, &'a Group);
+    type IntoIter = btree_map::Iter<'a, K, Group>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.groups.iter()
+    }
+}
+
+/// Group samples of tasks along with the IDs of their processes by `by`.
+pub fn group_by(samples: &[(u32, TaskStats)], by: GroupBy) -> Aggregate<Key> {
+    group_by_key(samples, |tgid, ts| by.key(tgid, ts))
+}
+
+/// Group stats of tasks by `by`, taking their processes from `ac_tgid` as
+/// `GroupBy::key_of` does.
+pub fn group_stats(stats: &[TaskStats], by: GroupBy) -> Aggregate<Key> {
+    let mut aggregate = Aggregate::new();
+    for ts in stats {
+        aggregate.add(by.key_of(ts), ts);
+    }
+    aggregate
+}
+
+/// Group samples of tasks along with the IDs of their processes by a key
+/// taken by `key`.
+pub fn group_by_key<K, F>(samples: &[(u32, TaskStats)], mut key: F) -> Aggregate<K>
+where
+    K: Ord,
+    F: FnMut(u32, &TaskStats) -> K,
+{
+    let mut aggregate = Aggregate::new();
+    for (tgid, ts) in samples {
+        aggregate.add(key(*tgid, ts), ts);
+    }
+    aggregate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, DelayStat, Delays};
+    use std::time::Duration;
+
+    fn stats(tid: u32, comm: &str, uid: u32, etime: u64) -> TaskStats {
+        TaskStats::builder()
+            .with_tid(tid)
+            .with_comm(comm)
+            .with_uid(uid)
+            .with_gid(100)
+            .with_ppid(1)
+            .with_elapsed(Duration::from_micros(etime))
+            .with_cpu(Cpu {
+                utime_total: Duration::from_micros(10),
+                ..Default::default()
+            })
+            .with_delays(Delays {
+                blkio: DelayStat {
+                    count: 1,
+                    delay_total: Duration::from_nanos(tid as u64),
+                },
+                ..Default::default()
+            })
+            .with_raw(|raw| raw.hiwater_rss = tid as u64)
+            .build()
+    }
+
+    #[test]
+    fn test_group_by() {
+        let samples = [
+            (10, stats(10, "sh", 0, 100)),
+            (11, stats(11, "cc", 1000, 200)),
+            (11, stats(12, "sh", 1000, 300)),
+        ];
+
+        let groups: Vec<_> = group_by(&samples, GroupBy::Comm).into_iter().collect();
+        let keys: Vec<_> = groups.iter().map(|(k, _)| k.to_string()).collect();
+        assert_eq!(vec!["cc", "sh"], keys);
+        let sh = &groups[1].1;
+        assert_eq!(2, sh.tasks);
+        assert_eq!(10, sh.stats.tid);
+        assert_eq!(Duration::from_micros(20), sh.stats.cpu.utime_total);
+        assert_eq!(Duration::from_nanos(22), sh.stats.delays.blkio.delay_total);
+        assert_eq!(2, sh.stats.delays.blkio.count);
+        assert_eq!(Duration::from_nanos(11), sh.stats.delays.blkio.average());
+        assert_eq!(Duration::from_micros(400), sh.stats.elapsed());
+        assert_eq!(12, sh.stats.inner().hiwater_rss);
+
+        let by_uid = group_by(&samples, GroupBy::Uid);
+        assert_eq!(2, by_uid.get(&Key::Id(1000)).unwrap().tasks);
+        let by_tgid = group_by(&samples, GroupBy::Tgid);
+        assert_eq!(2, by_tgid.get(&Key::Id(11)).unwrap().tasks);
+        assert_eq!(1, group_by(&samples, GroupBy::Gid).len());
+        assert_eq!(1, group_by(&samples, GroupBy::Ppid).len());
+    }
+
+    #[test]
+    fn test_group_stats_by_tgid() {
+        let thread = |tid, tgid| {
+            TaskStats::builder()
+                .with_tid(tid)
+                .with_tgid(tgid)
+                .with_comm("sh")
+                .build()
+        };
+        // The last one is of a record lacking ac_tgid, e.g. of an older kernel
+        let records = [
+            thread(10, 10),
+            thread(11, 10),
+            thread(12, 12),
+            thread(13, 0),
+        ];
+        let counts: Vec<_> = group_stats(&records, GroupBy::Tgid)
+            .into_iter()
+            .map(|(key, group)| (key, group.tasks))
+            .collect();
+        let expect: &[(u32, usize)] = if records[0].tgid().is_some() {
+            &[(10, 2), (12, 1), (13, 1)]
+        } else {
+            // Built against headers without ac_tgid, each task is a process
+            &[(10, 1), (11, 1), (12, 1), (13, 1)]
+        };
+        let expect: Vec<_> = expect.iter().map(|&(id, n)| (Key::Id(id), n)).collect();
+        assert_eq!(expect, counts);
+        assert_eq!(
+            4,
+            group_stats(&records, GroupBy::Comm)
+                .get(&Key::Name("sh".into()))
+                .unwrap()
+                .tasks
+        );
+    }
+
+    #[test]
+    fn test_key_order() {
+        let samples = [
+            (1, stats(1, "a", 1000, 0)),
+            (2, stats(2, "a", 2, 0)),
+            (3, stats(3, "a", 30, 0)),
+        ];
+        let keys: Vec<_> = group_by(&samples, GroupBy::Uid)
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(vec![Key::Id(2), Key::Id(30), Key::Id(1000)], keys);
+    }
+
+    #[test]
+    fn test_group_by_key() {
+        let samples = [
+            (1, stats(1, "a", 0, 0)),
+            (2, stats(2, "b", 0, 0)),
+            (3, stats(3, "c", 0, 0)),
+        ];
+        let mut odd = group_by_key(&samples, |tgid, _| tgid % 2 == 1);
+        assert_eq!(2, odd.get(&true).unwrap().tasks);
+
+        let even = *odd.get(&false).unwrap();
+        odd.merge(true, &even);
+        assert_eq!(3, odd.get(&true).unwrap().tasks);
+        assert_eq!(
+            Duration::from_nanos(6),
+            odd.get(&true).unwrap().stats.delays.blkio.delay_total
+        );
+    }
+}
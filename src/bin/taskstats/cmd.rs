@@ -1,12 +1,63 @@
-use linux_taskstats::format::{HeaderFormat, Printer};
-use linux_taskstats::Client;
+use linux_taskstats::container::{Enriched, Enricher, Record};
+use linux_taskstats::format::{Column, HeaderFormat, Order, Printer, Template, UnitStyle, Value};
+use linux_taskstats::procfs::ProcFs;
+use linux_taskstats::{Client, TaskStats};
 
-use std::io;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-pub struct Config<H: HeaderFormat> {
-    pub tids: Vec<u32>,
+#[derive(Clone, Copy)]
+pub enum Output {
+    Table,
+    Json,
+    Ndjson,
+    Csv,
+    Tsv,
+    Influx,
+}
+
+impl Output {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "table" => Output::Table,
+            "json" => Output::Json,
+            "ndjson" => Output::Ndjson,
+            "csv" => Output::Csv,
+            "tsv" => Output::Tsv,
+            "influx" => Output::Influx,
+            _ => panic!("unknown output format: {}", name),
+        }
+    }
+}
+
+pub struct PrintConfig {
     pub verbose: bool,
     pub show_delays: bool,
+    pub output: Output,
+    pub unit_style: UnitStyle,
+    pub precision: usize,
+    pub sort: Option<(&'static Column, Order)>,
+    pub top: Option<usize>,
+    pub thresholds: Vec<(&'static Column, Value)>,
+    pub columns: Option<Template>,
+    pub format: Option<Template>,
+    /// File to append output to instead of stdout
+    pub output_file: Option<PathBuf>,
+    pub procfs: Arc<dyn ProcFs>,
+}
+
+/// Columns of csv and tsv outputs unless specified
+const DEFAULT_COLUMNS: &str = "tid,comm,utime,stime,rss,vmem,read,write,\
+                               cpu_delay,blkio_delay,swapin_delay,freepages_delay";
+
+pub struct Config<H: HeaderFormat> {
+    pub tids: Vec<u32>,
+    /// Resolves containers of tasks if given
+    pub enricher: Option<Enricher>,
+    pub print: PrintConfig,
     pub header_format: H,
 }
 
@@ -17,28 +68,127 @@ pub fn taskstats_main<H: HeaderFormat>(config: Config<H>) {
     let client = Client::open().expect("netlink init");
     for pid in config.tids {
         let ts = client.pid_stats(pid).expect("get stats");
-        stats.push(ts);
+        stats.push(enrich(config.enricher.as_ref(), &ts));
     }
 
-    let printer = Printer::new(config.header_format);
+    let printer = printer(config.header_format, &config.print);
+    print_stats(&printer, &config.print, &stats);
+}
+
+/// Create a `Printer` configured by `config`
+pub fn printer<H: HeaderFormat>(header_format: H, config: &PrintConfig) -> Printer<H> {
+    let mut printer = Printer::new(header_format)
+        .with_unit_style(config.unit_style)
+        .with_precision(config.precision)
+        .with_procfs(Arc::clone(&config.procfs));
+    if let Some((column, order)) = config.sort {
+        printer = printer.with_sort(column, order);
+    }
+    if let Some(n) = config.top {
+        printer = printer.with_top(n);
+    }
+    for (column, min) in &config.thresholds {
+        printer = printer.with_min(column, min.clone());
+    }
+    printer
+}
+
+/// Enrich stats by `enricher` if given
+pub fn enrich(enricher: Option<&Enricher>, ts: &TaskStats) -> Enriched {
+    match enricher {
+        Some(enricher) => enricher.enrich(ts),
+        None => Enriched::from(*ts),
+    }
+}
+
+pub fn print_stats<H: HeaderFormat, R: Record>(
+    printer: &Printer<H>,
+    config: &PrintConfig,
+    stats: &[R],
+) {
+    print_stats_at(printer, config, stats, SystemTime::now())
+}
+
+/// Open the file to append output to if given, or stdout
+pub fn open_output(config: &PrintConfig) -> Box<dyn Write> {
+    match &config.output_file {
+        Some(path) => Box::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .expect("open output file"),
+        ),
+        None => Box::new(io::stdout()),
+    }
+}
+
+/// Print stats taken at `time`, which stamps lines of the influx output
+pub fn print_stats_at<H: HeaderFormat, R: Record>(
+    printer: &Printer<H>,
+    config: &PrintConfig,
+    stats: &[R],
+    time: SystemTime,
+) {
+    let out = &mut open_output(config);
+    match config.output {
+        Output::Json => {
+            printer.print_json(out, stats).expect("write output");
+            return;
+        }
+        Output::Ndjson => {
+            printer.print_ndjson(out, stats).expect("write output");
+            return;
+        }
+        Output::Csv | Output::Tsv => {
+            let columns = config
+                .columns
+                .clone()
+                .unwrap_or_else(|| Template::from_fields(DEFAULT_COLUMNS).unwrap())
+                .columns();
+            if let Output::Csv = config.output {
+                printer.print_csv(out, stats, &columns)
+            } else {
+                printer.print_tsv(out, stats, &columns)
+            }
+            .expect("write output");
+            return;
+        }
+        Output::Influx => {
+            printer
+                .print_influx(out, stats, time)
+                .expect("write output");
+            return;
+        }
+        Output::Table => {}
+    }
+
+    if let Some(format) = &config.format {
+        printer
+            .print_template_text(out, stats, format)
+            .expect("write output");
+        return;
+    }
+    if let Some(columns) = &config.columns {
+        printer
+            .print_template_table(out, stats, columns)
+            .expect("write output");
+        return;
+    }
 
     let mut show_line = true;
     if config.verbose {
-        printer
-            .print_full(&mut io::stdout(), &stats)
-            .expect("write stdout");
+        printer.print_full(out, stats).expect("write output");
         show_line = false;
     }
     if config.show_delays {
-        printer
-            .print_delay_lines(&mut io::stdout(), &stats)
-            .expect("write stdout");
+        printer.print_delay_lines(out, stats).expect("write output");
         show_line = false;
     }
 
     if show_line {
         printer
-            .print_summary_lines(&mut io::stdout(), &stats)
-            .expect("write stdout")
+            .print_summary_lines(out, stats)
+            .expect("write output")
     }
 }
@@ -0,0 +1,204 @@
+use crate::cmd::enrich;
+use crate::tree::all_cpus_mask;
+use linux_taskstats::container::{Enriched, Enricher};
+use linux_taskstats::format::prometheus::{Label, Metrics};
+use linux_taskstats::procfs::ProcFs;
+use linux_taskstats::{Client, TaskStats};
+use log::warn;
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Timeout of reading a request from a client
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where metrics are exposed
+pub enum Target {
+    /// Serve metrics over HTTP on the address
+    Http(String),
+    /// Write metrics to a file for node_exporter's textfile collector every interval
+    Textfile { path: PathBuf, interval: Duration },
+}
+
+pub struct Config {
+    pub target: Target,
+    pub labels: Vec<Label>,
+    pub max_series: usize,
+    /// Resolves containers of tasks if given
+    pub enricher: Option<Enricher>,
+    pub procfs: Arc<dyn ProcFs>,
+}
+
+pub fn exporter_main(config: Config) {
+    env_logger::init();
+
+    let Config {
+        target,
+        labels,
+        max_series,
+        enricher,
+        procfs,
+    } = config;
+    let metrics = Metrics::new(labels)
+        .with_max_series(max_series)
+        .with_procfs(Arc::clone(&procfs));
+    let metrics = Arc::new(Mutex::new(metrics));
+    let exits = Arc::clone(&metrics);
+    spawn_exit_listener(enricher.clone(), move |record| {
+        exits.lock().unwrap().add_exit(record)
+    });
+
+    let client = Client::open().expect("netlink init");
+    let enricher = enricher.as_ref();
+    let procfs = &*procfs;
+    match target {
+        Target::Http(listen) => {
+            let listener = TcpListener::bind(&listen).expect("bind listen address");
+            for stream in listener.incoming() {
+                let result =
+                    stream.and_then(|stream| serve(stream, &client, procfs, enricher, &metrics));
+                if let Err(e) = result {
+                    warn!("Failed to serve a request: {}", e);
+                }
+            }
+        }
+        Target::Textfile { path, interval } => loop {
+            if let Err(e) = write_textfile(&path, &client, procfs, enricher, &metrics) {
+                warn!("Failed to write {}: {}", path.display(), e);
+            }
+            thread::sleep(interval);
+        },
+    }
+}
+
+/// Call `on_exit` with every exit record of tasks on the host in background,
+/// enriched by `enricher` as soon as it arrives if given
+pub fn spawn_exit_listener<F: FnMut(&Enriched) + Send + 'static>(
+    enricher: Option<Enricher>,
+    mut on_exit: F,
+) {
+    thread::spawn(move || {
+        let client = Client::open().expect("netlink init");
+        client
+            .register_cpumask(&all_cpus_mask())
+            .expect("register cpumask");
+        loop {
+            match client.listen_registered() {
+                Ok(records) => records
+                    .iter()
+                    .for_each(|ts| on_exit(&enrich(enricher.as_ref(), ts))),
+                Err(e) => warn!("Failed to receive exit records: {}", e),
+            }
+        }
+    });
+}
+
+fn serve(
+    stream: TcpStream,
+    client: &Client,
+    procfs: &dyn ProcFs,
+    enricher: Option<&Enricher>,
+    metrics: &Mutex<Metrics>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut out = &stream;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    if path != "/metrics" {
+        let body = "Not Found. Metrics are served at /metrics\n";
+        return write!(
+            out,
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+    }
+
+    let body = render(client, procfs, enricher, metrics)?;
+    write!(
+        out,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    out.write_all(&body)
+}
+
+/// Write metrics to a temporary file next to `path` then rename it to `path`,
+/// so that readers never see a partially written file
+fn write_textfile(
+    path: &Path,
+    client: &Client,
+    procfs: &dyn ProcFs,
+    enricher: Option<&Enricher>,
+    metrics: &Mutex<Metrics>,
+) -> io::Result<()> {
+    let body = render(client, procfs, enricher, metrics)?;
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&body)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Sample all threads and render metrics in the text exposition format
+fn render(
+    client: &Client,
+    procfs: &dyn ProcFs,
+    enricher: Option<&Enricher>,
+    metrics: &Mutex<Metrics>,
+) -> io::Result<Vec<u8>> {
+    // Sample while holding the lock, so that a thread exiting meanwhile is
+    // counted either as a sample or as an exit but never as both
+    let mut metrics = metrics.lock().unwrap();
+    let samples = enrich_samples(sample_threads(client, procfs), enricher);
+    let mut body = Vec::new();
+    metrics.clear_samples();
+    for (pid, record) in &samples {
+        metrics.add_sample(*pid, record);
+    }
+    metrics.write(&mut body)?;
+    Ok(body)
+}
+
+/// Take stats of every thread on the host along with the ID of its process.
+///
+/// Tasks exiting while being sampled are skipped.
+pub fn sample_threads(client: &Client, procfs: &dyn ProcFs) -> Vec<(u32, TaskStats)> {
+    let mut samples = Vec::new();
+    for pid in procfs.pids().unwrap_or_default() {
+        for tid in procfs.tids(pid).unwrap_or_default() {
+            if let Ok(ts) = client.pid_stats(tid) {
+                samples.push((pid, ts));
+            }
+        }
+    }
+    samples
+}
+
+/// Enrich samples by `enricher` if given
+pub fn enrich_samples(
+    samples: Vec<(u32, TaskStats)>,
+    enricher: Option<&Enricher>,
+) -> Vec<(u32, Enriched)> {
+    samples
+        .into_iter()
+        .map(|(pid, ts)| (pid, enrich(enricher, &ts)))
+        .collect()
+}
@@ -0,0 +1,47 @@
+use crate::tree::all_cpus_mask;
+use linux_taskstats::journal::JournalWriter;
+use linux_taskstats::Client;
+use log::warn;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub struct Config {
+    pub dir: PathBuf,
+    pub max_size: u64,
+    pub max_age: Option<Duration>,
+    pub max_segments: Option<usize>,
+}
+
+pub fn journal_main(config: Config) {
+    env_logger::init();
+
+    let mut writer = JournalWriter::open(&config.dir)
+        .expect("open journal")
+        .with_max_size(config.max_size);
+    if let Some(age) = config.max_age {
+        writer = writer.with_max_age(age);
+    }
+    if let Some(n) = config.max_segments {
+        writer = writer.with_max_segments(n);
+    }
+
+    let client = Client::open().expect("netlink init");
+    client
+        .register_cpumask(&all_cpus_mask())
+        .expect("register cpumask");
+    loop {
+        let records = match client.listen_registered() {
+            Ok(records) => records,
+            Err(e) => {
+                warn!("Failed to receive exit records: {}", e);
+                continue;
+            }
+        };
+        // Per-tgid records lack most of the fields
+        for ts in records.iter().filter(|ts| ts.tid != 0) {
+            writer.append(ts).expect("append to journal");
+        }
+        writer.flush().expect("write journal");
+    }
+}
@@ -1,14 +1,56 @@
-use clap::{Arg, ArgAction, Command};
-use linux_taskstats::format::DefaultHeaderFormat;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use linux_taskstats::aggregate::GroupBy;
+use linux_taskstats::container::Enricher;
+use linux_taskstats::format::prometheus::Label;
+use linux_taskstats::format::{
+    CgroupHeaderFormat, CmdlineHeaderFormat, Column, CommHeaderFormat, ContainerHeaderFormat,
+    DefaultHeaderFormat, Field, HeaderFormat, Order, Template, UnitStyle, UserHeaderFormat, Value,
+};
+use linux_taskstats::procfs::{ProcDir, ProcFs};
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 mod cmd;
+mod exporter;
+mod journal;
+#[cfg(feature = "otel")]
+mod otel;
+mod record;
+mod report;
+mod statsd;
+mod tree;
 
-fn main() {
-    let matches = Command::new("A command line interface to Linux taskstats")
+/// Argument of where proc is mounted, read by every subcommand looking into tasks
+fn proc_root_arg() -> Arg {
+    Arg::new("proc-root")
+        .long("proc-root")
+        .help("Read tasks from proc mounted at a directory, e.g. the host's one mounted at /host/proc in a container")
+        .long_help(
+            "Read tasks from proc mounted at a directory, e.g. the host's one mounted at /host/proc in a container.\n\n\
+             The taskstats netlink family is available only in the initial network namespace, and resolves \
+             task IDs in the PID namespace of the caller. So running in a container needs the host's network \
+             and PID namespaces (e.g. --net=host --pid=host), otherwise IDs read from the host's proc don't \
+             match the tasks the kernel reports.",
+        )
+        .value_parser(value_parser!(PathBuf))
+        .default_value("/proc")
+}
+
+fn procfs(matches: &ArgMatches) -> Arc<dyn ProcFs> {
+    let root = matches.get_one::<PathBuf>("proc-root").unwrap();
+    Arc::new(ProcDir::new(root))
+}
+
+/// Add arguments controlling how stats are printed
+fn print_args(command: Command) -> Command {
+    command
+        .arg(proc_root_arg())
         .arg(
             Arg::new("verbose")
                 .short('v')
-                .long("verfbose")
+                .long("verbose")
                 .action(ArgAction::SetTrue),
         )
         .arg(
@@ -17,25 +59,616 @@ fn main() {
                 .long("delay")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_parser(["table", "json", "ndjson", "csv", "tsv", "influx"])
+                .default_value("table"),
+        )
+        .arg(
+            Arg::new("output-file")
+                .long("output-file")
+                .help("Append output to a file instead of printing to stdout")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("label")
+                .long("label")
+                .help("How to label each task")
+                .value_parser(["tid", "comm", "cmdline", "user", "cgroup", "container"])
+                .default_value("tid"),
+        )
+        .arg(
+            Arg::new("units")
+                .long("units")
+                .help("Style of rendering durations and sizes in table, csv and tsv outputs")
+                .value_parser(["raw", "human", "si"])
+                .default_value("raw"),
+        )
+        .arg(
+            Arg::new("precision")
+                .long("precision")
+                .help("Number of fractional digits of values rendered in human or si units")
+                .value_parser(value_parser!(usize))
+                .default_value("1"),
+        )
+        .arg(
+            Arg::new("columns")
+                .short('o')
+                .long("columns")
+                .help(columns_help())
+                .value_parser(|s: &str| Template::from_fields(s)),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .help("Sort tasks by a column, descending when prefixed by '-' e.g. -cpu_delay")
+                .allow_hyphen_values(true)
+                .value_parser(parse_sort),
+        )
+        .arg(
+            Arg::new("top")
+                .long("top")
+                .help("Print only the first N tasks")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("min")
+                .long("min")
+                .help("Hide tasks having a column less than a value, e.g. cpu_delay=10ms")
+                .value_parser(parse_min)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("min-cpu-delay")
+                .long("min-cpu-delay")
+                .help("Hide tasks having total CPU delay less than a duration, e.g. 10ms. Same as --min cpu_delay=DURATION")
+                .value_parser(|s: &str| parse_min(&format!("cpu_delay={}", s))),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("Print each task of the table output by a format string like \"%tid %comm %cpu_delay_avg\\n\"")
+                .conflicts_with("columns")
+                .value_parser(|s: &str| Template::parse(&s.replace("\\n", "\n"))),
+        )
+}
+
+/// Add arguments controlling metrics of all tasks on the host
+fn metrics_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("labels")
+                .long("labels")
+                .help("Comma separated labels to aggregate tasks by in metrics [possible values: pid, comm, user, cgroup, container, pod]")
+                .value_parser(parse_labels)
+                .default_value("comm"),
+        )
+        .arg(
+            Arg::new("max-series")
+                .long("max-series")
+                .help("Maximum number of label sets per metric, the rest is labeled \"other\"")
+                .value_parser(value_parser!(usize))
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("containers")
+                .long("containers")
+                .help("Resolve containers, pods and PID namespaces of tasks from proc as their stats and exits arrive")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+fn enricher(matches: &ArgMatches) -> Option<Enricher> {
+    if matches.get_flag("containers") {
+        Some(Enricher::new().with_procfs(procfs(matches)))
+    } else {
+        None
+    }
+}
+
+fn exporter_config(matches: &ArgMatches, target: exporter::Target) -> exporter::Config {
+    exporter::Config {
+        target,
+        labels: matches.get_one::<Vec<Label>>("labels").unwrap().clone(),
+        max_series: *matches.get_one::<usize>("max-series").unwrap(),
+        enricher: enricher(matches),
+        procfs: procfs(matches),
+    }
+}
+
+fn columns_help() -> String {
+    let names: Vec<_> = Column::all().iter().map(|c| c.name).collect();
+    format!(
+        "Comma separated columns to print in table, csv and tsv outputs, \
+         each optionally followed by =TITLE, where task is only for the table output \
+         [possible values: task, {}]",
+        names.join(", ")
+    )
+}
+
+fn parse_sort(s: &str) -> Result<(&'static Column, Order), String> {
+    let (name, order) = match s.strip_prefix('-') {
+        Some(name) => (name, Order::Descending),
+        None => (s.strip_prefix('+').unwrap_or(s), Order::Ascending),
+    };
+    let column = Column::by_name(name).ok_or_else(|| format!("unknown column: {}", name))?;
+    Ok((column, order))
+}
+
+fn parse_min(s: &str) -> Result<(&'static Column, Value), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected COLUMN=VALUE: {}", s))?;
+    let column = Column::by_name(name).ok_or_else(|| format!("unknown column: {}", name))?;
+    let min = column
+        .parse_value(value)
+        .ok_or_else(|| format!("invalid value for {}: {}", name, value))?;
+    Ok((column, min))
+}
+
+fn parse_labels(s: &str) -> Result<Vec<Label>, String> {
+    s.split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| Label::from_name(name).ok_or_else(|| format!("unknown label: {}", name)))
+        .collect()
+}
+
+/// Parse a UNIX time in seconds, or a duration ago suffixed by s, m, h or d
+fn parse_time(s: &str) -> Result<SystemTime, String> {
+    let out_of_range = || format!("time out of range: {}", s);
+    if let Ok(secs) = s.parse::<u64>() {
+        return UNIX_EPOCH
+            .checked_add(Duration::from_secs(secs))
+            .ok_or_else(out_of_range);
+    }
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+    let scale = match suffix {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("expected UNIX time or duration like 2h: {}", s)),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected UNIX time or duration like 2h: {}", s))?;
+    let secs = number.checked_mul(scale).ok_or_else(out_of_range)?;
+    SystemTime::now()
+        .checked_sub(Duration::from_secs(secs))
+        .ok_or_else(out_of_range)
+}
+
+/// Max length of command lines labeling tasks
+const CMDLINE_LABEL_LEN: usize = 40;
+
+fn header_format(matches: &ArgMatches) -> Box<dyn HeaderFormat> {
+    let procfs = procfs(matches);
+    match matches.get_one::<String>("label").unwrap().as_str() {
+        "comm" => Box::new(CommHeaderFormat::new()),
+        "cmdline" => Box::new(CmdlineHeaderFormat::new(CMDLINE_LABEL_LEN).with_procfs(procfs)),
+        "user" => Box::new(UserHeaderFormat::new()),
+        "cgroup" => Box::new(CgroupHeaderFormat::new().with_procfs(procfs)),
+        "container" => Box::new(ContainerHeaderFormat::new().with_procfs(procfs)),
+        _ => Box::new(DefaultHeaderFormat::new()),
+    }
+}
+
+fn print_config(matches: &ArgMatches) -> cmd::PrintConfig {
+    let output = cmd::Output::from_name(matches.get_one::<String>("output").unwrap());
+    let columns = matches.get_one::<Template>("columns");
+    let format = matches.get_one::<Template>("format");
+    let has_task = columns.is_some_and(|t| t.fields().any(|(f, _)| matches!(f, Field::Task)));
+    match output {
+        cmd::Output::Table => {}
+        cmd::Output::Csv | cmd::Output::Tsv if has_task => {
+            conflict("--columns task is only for the table output")
+        }
+        _ if format.is_some() => conflict("--format is only for the table output"),
+        _ => {}
+    }
+
+    cmd::PrintConfig {
+        verbose: matches.get_flag("verbose"),
+        show_delays: matches.get_flag("show-delays"),
+        output,
+        unit_style: match matches.get_one::<String>("units").unwrap().as_str() {
+            "human" => UnitStyle::Human,
+            "si" => UnitStyle::Si,
+            _ => UnitStyle::Raw,
+        },
+        precision: *matches.get_one::<usize>("precision").unwrap(),
+        sort: matches.get_one::<(&Column, Order)>("sort").copied(),
+        top: matches.get_one::<usize>("top").copied(),
+        thresholds: matches
+            .get_many::<(&Column, Value)>("min")
+            .into_iter()
+            .flatten()
+            .chain(matches.get_one::<(&Column, Value)>("min-cpu-delay"))
+            .cloned()
+            .collect(),
+        columns: columns.cloned(),
+        format: format.cloned(),
+        output_file: matches.get_one::<PathBuf>("output-file").cloned(),
+        procfs: procfs(matches),
+    }
+}
+
+/// Exit with an error of arguments which can't be used together
+fn conflict(msg: &str) -> ! {
+    clap::Error::raw(
+        clap::error::ErrorKind::ArgumentConflict,
+        format!("{}\n", msg),
+    )
+    .exit()
+}
+
+fn main() {
+    let command = print_args(Command::new("A command line interface to Linux taskstats"));
+    let command = metrics_args(command)
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .arg(
             Arg::new("TIDS")
                 .index(1)
                 .num_args(1..)
-                .action(ArgAction::Append),
+                .value_parser(value_parser!(u32))
+                .action(ArgAction::Append)
+                .required_unless_present_any(["prometheus-textfile", "statsd"]),
+        )
+        .arg(
+            Arg::new("prometheus-textfile")
+                .long("prometheus-textfile")
+                .help("Write metrics of all tasks on the host to a .prom file for node_exporter's textfile collector every interval, instead of printing TIDS")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("TIDS"),
         )
-        .get_matches();
+        .arg(
+            Arg::new("statsd")
+                .long("statsd")
+                .help("Send metrics of all tasks on the host every interval and of every exit to a StatsD server at HOST:PORT, instead of printing TIDS")
+                .conflicts_with_all(["TIDS", "prometheus-textfile"]),
+        )
+        .arg(
+            Arg::new("statsd-prefix")
+                .long("statsd-prefix")
+                .help("Prefix of StatsD metric names")
+                .default_value("taskstats"),
+        )
+        .arg(
+            Arg::new("statsd-tags")
+                .long("statsd-tags")
+                .help("Comma separated tags of StatsD metrics in the DogStatsD format [possible values: pid, comm, user, cgroup, container, pod]")
+                .value_parser(parse_labels)
+                .default_value("comm,cgroup"),
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .help("Seconds between samples of metrics")
+                .value_parser(value_parser!(u64))
+                .default_value("15"),
+        )
+        .subcommand(
+            print_args(Command::new("tree"))
+                .about("Run a command and account all of its descendants until it exits. The rolled-up total is printed last, with TID 0")
+                .arg(
+                    Arg::new("COMMAND")
+                        .index(1)
+                        .num_args(1..)
+                        .required(true)
+                        .trailing_var_arg(true)
+                        .action(ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            metrics_args(Command::new("exporter"))
+                .about("Serve metrics of all tasks on the host at /metrics in the Prometheus text format")
+                .arg(proc_root_arg())
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .help("Address to listen on")
+                        .default_value("127.0.0.1:9816"),
+                ),
+        )
+        .subcommand(
+            Command::new("journal")
+                .about("Append exit records of all tasks on the host to a journal in DIR")
+                .arg(
+                    Arg::new("DIR")
+                        .index(1)
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("max-size")
+                        .long("max-size")
+                        .help("Start a new segment once the current one grows to the bytes")
+                        .value_parser(value_parser!(u64))
+                        .default_value("67108864"),
+                )
+                .arg(
+                    Arg::new("max-age")
+                        .long("max-age")
+                        .help("Start a new segment once the current one gets older than the seconds")
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("max-segments")
+                        .long("max-segments")
+                        .help("Remove the oldest segments beyond the number")
+                        .value_parser(value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            print_args(Command::new("report"))
+                .about("Report exit records in a journal written by the journal subcommand")
+                // Others are read from /proc, where the tasks have long gone
+                .mut_arg("label", |arg| {
+                    arg.help("How to label each task, by what is recorded")
+                        .value_parser(["tid", "comm"])
+                })
+                .arg(
+                    Arg::new("JOURNAL")
+                        .index(1)
+                        .required(true)
+                        .help("Journal directory or segment file")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("pacct")
+                        .long("pacct")
+                        .help("Read JOURNAL as a process accounting file written by acct(2), which has no delays")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("by")
+                        .long("by")
+                        .help("Sum tasks up per a key in the table output, by comm unless given")
+                        .value_parser(["task", "comm", "uid", "gid", "tgid", "ppid"]),
+                )
+                .arg(
+                    Arg::new("longest")
+                        .long("longest")
+                        .help("Print the N longest-lived tasks")
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with("by"),
+                )
+                .arg(
+                    Arg::new("most-blkio-delay")
+                        .long("most-blkio-delay")
+                        .help("Print the N tasks delayed the most by block I/O")
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with_all(["by", "longest"]),
+                )
+                .arg(
+                    Arg::new("histogram")
+                        .long("histogram")
+                        .help("Print a histogram of lifetimes of tasks")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["by", "longest", "most-blkio-delay"]),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .help("Report tasks exited since a UNIX time in seconds, or a duration ago like 2h")
+                        .value_parser(parse_time),
+                )
+                .arg(
+                    Arg::new("until")
+                        .long("until")
+                        .help("Report tasks exited until a UNIX time in seconds, or a duration ago like 30m")
+                        .value_parser(parse_time),
+                ),
+        )
+        .subcommand(
+            Command::new("record")
+                .about("Record stats of all tasks on the host every interval to a file, to be replayed later")
+                .arg(proc_root_arg())
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .required(true)
+                        .help("File to record to, truncated if exists")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .help("Seconds between snapshots")
+                        .value_parser(value_parser!(u64))
+                        .default_value("15"),
+                )
+                .arg(
+                    Arg::new("count")
+                        .long("count")
+                        .help("Number of snapshots to record, forever unless given")
+                        .value_parser(value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            print_args(Command::new("replay"))
+                .about("Print stats recorded by the record subcommand, a snapshot after another")
+                // Others are read from /proc of this host, not of the recorded one
+                .mut_arg("label", |arg| {
+                    arg.help("How to label each task, by what is recorded")
+                        .value_parser(["tid", "comm"])
+                })
+                .arg(
+                    Arg::new("FILE")
+                        .index(1)
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("TIDS")
+                        .index(2)
+                        .num_args(1..)
+                        .help("Tasks to print, all unless given")
+                        .value_parser(value_parser!(u32))
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("realtime")
+                        .long("realtime")
+                        .help("Wait between snapshots as long as they were recorded apart")
+                        .action(ArgAction::SetTrue),
+                ),
+        );
+    #[cfg(feature = "otel")]
+    let command = command.arg(
+        Arg::new("otlp-endpoint")
+            .long("otlp-endpoint")
+            .help("Export metrics of all tasks on the host every interval to an OTLP/HTTP endpoint like http://localhost:4318, instead of printing TIDS")
+            .conflicts_with_all(["TIDS", "prometheus-textfile", "statsd"]),
+    )
+    .mut_arg("TIDS", |arg| arg.required_unless_present_any(["otlp-endpoint"]));
+    let matches = command.get_matches();
+
+    if let Some(matches) = matches.subcommand_matches("tree") {
+        let command: Vec<_> = matches
+            .get_many::<String>("COMMAND")
+            .unwrap()
+            .cloned()
+            .collect();
+        let config = tree::Config {
+            command,
+            print: print_config(matches),
+            header_format: header_format(matches),
+        };
+        tree::tree_main(config);
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("exporter") {
+        let listen = matches.get_one::<String>("listen").unwrap().clone();
+        let config = exporter_config(matches, exporter::Target::Http(listen));
+        exporter::exporter_main(config);
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("journal") {
+        let config = journal::Config {
+            dir: matches.get_one::<PathBuf>("DIR").unwrap().clone(),
+            max_size: *matches.get_one::<u64>("max-size").unwrap(),
+            max_age: matches
+                .get_one::<u64>("max-age")
+                .map(|secs| Duration::from_secs(*secs)),
+            max_segments: matches.get_one::<usize>("max-segments").copied(),
+        };
+        journal::journal_main(config);
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("report") {
+        let print = print_config(matches);
+        let view = if let Some(n) = matches.get_one::<usize>("longest") {
+            report::View::Longest(*n)
+        } else if let Some(n) = matches.get_one::<usize>("most-blkio-delay") {
+            report::View::MostBlkioDelay(*n)
+        } else if matches.get_flag("histogram") {
+            report::View::Histogram
+        } else {
+            // Rows of the other outputs have nowhere to put the key of groups
+            let by = match (matches.get_one::<String>("by"), &print.output) {
+                (Some(by), cmd::Output::Table) => by.as_str(),
+                (Some(by), _) if by != "task" => conflict("--by is only for the table output"),
+                (_, cmd::Output::Table) => "comm",
+                _ => "task",
+            };
+            match GroupBy::from_name(by) {
+                Some(by) => report::View::Groups(by),
+                None => report::View::Tasks,
+            }
+        };
+        let config = report::Config {
+            journal: matches.get_one::<PathBuf>("JOURNAL").unwrap().clone(),
+            pacct: matches.get_flag("pacct"),
+            since: matches.get_one::<SystemTime>("since").copied(),
+            until: matches.get_one::<SystemTime>("until").copied(),
+            view,
+            print,
+            header_format: header_format(matches),
+        };
+        report::report_main(config);
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("record") {
+        let config = record::RecordConfig {
+            output: matches.get_one::<PathBuf>("output").unwrap().clone(),
+            interval: Duration::from_secs(*matches.get_one::<u64>("interval").unwrap()),
+            count: matches.get_one::<usize>("count").copied(),
+            procfs: procfs(matches),
+        };
+        record::record_main(config);
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("replay") {
+        let config = record::ReplayConfig {
+            input: matches.get_one::<PathBuf>("FILE").unwrap().clone(),
+            tids: matches
+                .get_many::<u32>("TIDS")
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect(),
+            realtime: matches.get_flag("realtime"),
+            print: print_config(matches),
+            header_format: header_format(matches),
+        };
+        record::replay_main(config);
+        return;
+    }
+
+    if let Some(path) = matches.get_one::<PathBuf>("prometheus-textfile") {
+        let target = exporter::Target::Textfile {
+            path: path.clone(),
+            interval: Duration::from_secs(*matches.get_one::<u64>("interval").unwrap()),
+        };
+        exporter::exporter_main(exporter_config(&matches, target));
+        return;
+    }
+
+    if let Some(address) = matches.get_one::<String>("statsd") {
+        let config = statsd::Config {
+            address: address.clone(),
+            prefix: matches.get_one::<String>("statsd-prefix").unwrap().clone(),
+            tags: matches
+                .get_one::<Vec<Label>>("statsd-tags")
+                .unwrap()
+                .clone(),
+            interval: Duration::from_secs(*matches.get_one::<u64>("interval").unwrap()),
+            enricher: enricher(&matches),
+            procfs: procfs(&matches),
+        };
+        statsd::statsd_main(config);
+        return;
+    }
+
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = matches.get_one::<String>("otlp-endpoint") {
+        let config = otel::Config {
+            endpoint: endpoint.clone(),
+            interval: Duration::from_secs(*matches.get_one::<u64>("interval").unwrap()),
+            enricher: enricher(&matches),
+            procfs: procfs(&matches),
+        };
+        otel::otel_main(config);
+        return;
+    }
 
-    let tids: Vec<_> = matches
-        .get_many::<u32>("TIDS")
-        .unwrap()
-        .map(|x| *x)
-        .collect();
+    let tids: Vec<_> = matches.get_many::<u32>("TIDS").unwrap().copied().collect();
 
     let config = cmd::Config {
         tids,
-        verbose: matches.contains_id("verbose"),
-        show_delays: matches.contains_id("show-delays"),
-        header_format: DefaultHeaderFormat::new(),
+        enricher: enricher(&matches),
+        print: print_config(&matches),
+        header_format: header_format(&matches),
     };
     cmd::taskstats_main(config);
 }
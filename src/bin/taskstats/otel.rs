@@ -0,0 +1,57 @@
+use crate::exporter::{enrich_samples, sample_threads, spawn_exit_listener};
+use linux_taskstats::container::Enricher;
+use linux_taskstats::otel::OtlpExporter;
+use linux_taskstats::procfs::ProcFs;
+use linux_taskstats::Client;
+use log::warn;
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Maximum number of exit records kept while exports fail
+const MAX_PENDING_EXITS: usize = 100_000;
+
+pub struct Config {
+    pub endpoint: String,
+    pub interval: Duration,
+    /// Resolves containers of tasks if given
+    pub enricher: Option<Enricher>,
+    pub procfs: Arc<dyn ProcFs>,
+}
+
+pub fn otel_main(config: Config) {
+    env_logger::init();
+
+    let mut exporter = OtlpExporter::new(&config.endpoint).expect("OTLP endpoint");
+    let exits = Arc::new(Mutex::new(Vec::new()));
+    let pending = Arc::clone(&exits);
+    spawn_exit_listener(config.enricher.clone(), move |record| {
+        pending.lock().unwrap().push(record.clone())
+    });
+
+    let client = Client::open().expect("netlink init");
+    loop {
+        let samples = enrich_samples(
+            sample_threads(&client, &*config.procfs),
+            config.enricher.as_ref(),
+        );
+        let records = mem::take(&mut *exits.lock().unwrap());
+        if let Err(e) = exporter.export(&samples, &records) {
+            warn!("Failed to export metrics: {}", e);
+            // Give exit records to the next export, dropping the oldest ones
+            // if the collector has been failing for long
+            let mut exits = exits.lock().unwrap();
+            let mut records = records;
+            records.append(&mut exits);
+            let excess = records.len().saturating_sub(MAX_PENDING_EXITS);
+            if excess > 0 {
+                warn!("Dropping {} exit records failed to export", excess);
+                records.drain(..excess);
+            }
+            *exits = records;
+        }
+        thread::sleep(config.interval);
+    }
+}
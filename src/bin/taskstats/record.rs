@@ -0,0 +1,141 @@
+use crate::cmd::{print_stats_at, printer, Output, PrintConfig};
+use crate::exporter::sample_threads;
+use linux_taskstats::format::HeaderFormat;
+use linux_taskstats::procfs::ProcFs;
+use linux_taskstats::recording::{RecordingReader, RecordingWriter};
+use linux_taskstats::{Client, TaskStats};
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct RecordConfig {
+    pub output: PathBuf,
+    pub interval: Duration,
+    /// Number of snapshots to take, or forever
+    pub count: Option<usize>,
+    pub procfs: Arc<dyn ProcFs>,
+}
+
+pub struct ReplayConfig<H: HeaderFormat> {
+    pub input: PathBuf,
+    /// Tasks to print, or all
+    pub tids: Vec<u32>,
+    /// Sleep between snapshots as long as they were taken apart
+    pub realtime: bool,
+    pub print: PrintConfig,
+    pub header_format: H,
+}
+
+pub fn record_main(config: RecordConfig) {
+    env_logger::init();
+
+    let client = Client::open().expect("netlink init");
+    let mut writer = RecordingWriter::create(&config.output).expect("create recording");
+    let mut taken = 0;
+    while config.count.map_or(true, |count| taken < count) {
+        if taken > 0 {
+            thread::sleep(config.interval);
+        }
+        let samples = sample_threads(&client, &*config.procfs);
+        writer
+            .write(SystemTime::now(), &samples)
+            .expect("write recording");
+        taken += 1;
+    }
+}
+
+pub fn replay_main<H: HeaderFormat>(config: ReplayConfig<H>) {
+    env_logger::init();
+
+    let recorded = Arc::new(Recorded::default());
+    let printer = printer(config.header_format, &config.print).with_procfs(recorded.clone());
+    let tids = &config.tids;
+    let mut previous: Option<SystemTime> = None;
+    for snapshot in RecordingReader::open(&config.input).expect("open recording") {
+        let snapshot = snapshot.expect("read recording");
+        if let (true, Some(previous)) = (config.realtime, previous) {
+            thread::sleep(snapshot.time.duration_since(previous).unwrap_or_default());
+        }
+        previous = Some(snapshot.time);
+        recorded.set(&snapshot.samples);
+
+        let stats: Vec<_> = snapshot
+            .samples
+            .iter()
+            .map(|(_, ts)| *ts)
+            .filter(|ts| tids.is_empty() || tids.contains(&ts.tid))
+            .collect();
+        if let Output::Table = config.print.output {
+            println!("--- {}", format_time(snapshot.time));
+        }
+        print_stats_at(&printer, &config.print, &stats, snapshot.time);
+    }
+}
+
+/// Processes of tasks in the snapshot being replayed, told to the printer
+/// instead of reading /proc of this host
+#[derive(Default)]
+struct Recorded {
+    tgids: Mutex<HashMap<u32, u32>>,
+}
+
+impl Recorded {
+    fn set(&self, samples: &[(u32, TaskStats)]) {
+        *self.tgids.lock().unwrap() = samples.iter().map(|(pid, ts)| (ts.tid, *pid)).collect();
+    }
+}
+
+impl ProcFs for Recorded {
+    fn read_task_file(&self, _tid: u32, _name: &str) -> io::Result<Vec<u8>> {
+        Err(io::ErrorKind::NotFound.into())
+    }
+
+    fn read_task_link(&self, _tid: u32, _name: &str) -> io::Result<PathBuf> {
+        Err(io::ErrorKind::NotFound.into())
+    }
+
+    fn pids(&self) -> io::Result<Vec<u32>> {
+        let mut pids: Vec<_> = self.tgids.lock().unwrap().values().copied().collect();
+        pids.sort_unstable();
+        pids.dedup();
+        Ok(pids)
+    }
+
+    fn tids(&self, pid: u32) -> io::Result<Vec<u32>> {
+        let tgids = self.tgids.lock().unwrap();
+        Ok(tgids
+            .iter()
+            .filter(|(_, tgid)| **tgid == pid)
+            .map(|(tid, _)| *tid)
+            .collect())
+    }
+
+    fn tgid(&self, tid: u32) -> Option<u32> {
+        self.tgids.lock().unwrap().get(&tid).copied()
+    }
+}
+
+/// Format a time like `2023-03-28 10:40:00` in the local time zone
+fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    let mut buf = [0 as libc::c_char; 64];
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        libc::strftime(
+            buf.as_mut_ptr(),
+            buf.len(),
+            b"%Y-%m-%d %H:%M:%S\0".as_ptr() as *const libc::c_char,
+            &tm,
+        );
+        CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+    }
+}
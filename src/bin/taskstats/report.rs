@@ -0,0 +1,120 @@
+use crate::cmd::{open_output, print_stats, printer, PrintConfig};
+use linux_taskstats::acct::AcctReader;
+use linux_taskstats::aggregate::{self, GroupBy, Key};
+use linux_taskstats::format::{Column, HeaderFormat, Order};
+use linux_taskstats::journal::JournalReader;
+use linux_taskstats::report;
+use linux_taskstats::TaskStats;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// What to report out of exit records
+pub enum View {
+    /// Every task
+    Tasks,
+    /// Sum of tasks per key
+    Groups(GroupBy),
+    /// The N longest-lived tasks
+    Longest(usize),
+    /// The N tasks delayed the most by block I/O
+    MostBlkioDelay(usize),
+    /// Histogram of lifetimes of tasks
+    Histogram,
+}
+
+pub struct Config<H: HeaderFormat> {
+    pub journal: PathBuf,
+    /// Whether `journal` is a process accounting file instead
+    pub pacct: bool,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+    pub view: View,
+    pub print: PrintConfig,
+    pub header_format: H,
+}
+
+/// Labels a group by its key and the number of tasks in it, e.g. `uid 1000 (12 tasks)`.
+struct GroupHeaderFormat {
+    by: GroupBy,
+    counts: HashMap<Key, usize>,
+}
+
+impl HeaderFormat for GroupHeaderFormat {
+    fn format(&self, ts: &TaskStats) -> String {
+        let key = self.by.key_of(ts);
+        let count = self.counts.get(&key).copied().unwrap_or(0);
+        match self.by {
+            GroupBy::Comm => format!("{} ({} tasks)", key, count),
+            _ => format!("{} {} ({} tasks)", self.by.name(), key, count),
+        }
+    }
+}
+
+pub fn report_main<H: HeaderFormat>(mut config: Config<H>) {
+    env_logger::init();
+
+    // Pairs of exit time and stats
+    let entries: Vec<(SystemTime, TaskStats)> = if config.pacct {
+        AcctReader::open(&config.journal)
+            .expect("open pacct")
+            .map(|record| {
+                let record = record.expect("read pacct");
+                (record.begin_time + record.elapsed, TaskStats::from(&record))
+            })
+            .collect()
+    } else {
+        JournalReader::open(&config.journal)
+            .expect("open journal")
+            .map(|entry| {
+                let entry = entry.expect("read journal");
+                (entry.time, entry.stats)
+            })
+            .collect()
+    };
+    let mut records = Vec::new();
+    for (time, stats) in entries {
+        let in_window = config.since.map_or(true, |since| time >= since)
+            && config.until.map_or(true, |until| time < until);
+        // Per-tgid records lack most of the fields
+        if in_window && stats.tid != 0 {
+            records.push(stats);
+        }
+    }
+
+    match config.view {
+        View::Tasks => {}
+        View::Groups(by) => {
+            let groups = aggregate::group_stats(&records, by);
+            let header_format = GroupHeaderFormat {
+                by,
+                counts: groups.iter().map(|(k, g)| (k.clone(), g.tasks)).collect(),
+            };
+            let stats: Vec<_> = groups.iter().map(|(_, g)| g.stats).collect();
+            let printer = printer(header_format, &config.print);
+            print_stats(&printer, &config.print, &stats);
+            return;
+        }
+        View::Longest(n) => {
+            config.print.sort = Some((Column::by_name("elapsed").unwrap(), Order::Descending));
+            config.print.top = Some(n);
+        }
+        View::MostBlkioDelay(n) => {
+            config.print.sort = Some((Column::by_name("blkio_delay").unwrap(), Order::Descending));
+            config.print.top = Some(n);
+        }
+        View::Histogram => {
+            let printer = printer(config.header_format, &config.print);
+            printer
+                .print_histogram(
+                    &mut open_output(&config.print),
+                    &report::lifetime_histogram(&records),
+                )
+                .expect("write output");
+            return;
+        }
+    }
+    let printer = printer(config.header_format, &config.print);
+    print_stats(&printer, &config.print, &records);
+}
@@ -0,0 +1,51 @@
+use crate::exporter::{enrich_samples, sample_threads, spawn_exit_listener};
+use linux_taskstats::container::Enricher;
+use linux_taskstats::format::prometheus::Label;
+use linux_taskstats::format::statsd::StatsdSink;
+use linux_taskstats::procfs::ProcFs;
+use linux_taskstats::Client;
+use log::warn;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub struct Config {
+    pub address: String,
+    pub prefix: String,
+    pub tags: Vec<Label>,
+    pub interval: Duration,
+    /// Resolves containers of tasks if given
+    pub enricher: Option<Enricher>,
+    pub procfs: Arc<dyn ProcFs>,
+}
+
+pub fn statsd_main(config: Config) {
+    env_logger::init();
+
+    // Shared by exits to send the increase since the last sample of the task
+    let sink = StatsdSink::connect(&config.address)
+        .expect("connect statsd")
+        .with_prefix(&config.prefix)
+        .with_tags(config.tags.clone())
+        .with_procfs(Arc::clone(&config.procfs));
+    let sink = Arc::new(Mutex::new(sink));
+    let exit_sink = Arc::clone(&sink);
+    spawn_exit_listener(config.enricher.clone(), move |record| {
+        if let Err(e) = exit_sink.lock().unwrap().send_exit(record) {
+            warn!("Failed to send exit metrics: {}", e);
+        }
+    });
+
+    let client = Client::open().expect("netlink init");
+    loop {
+        let samples = enrich_samples(
+            sample_threads(&client, &*config.procfs),
+            config.enricher.as_ref(),
+        );
+        if let Err(e) = sink.lock().unwrap().send_samples(&samples) {
+            warn!("Failed to send metrics: {}", e);
+        }
+        thread::sleep(config.interval);
+    }
+}
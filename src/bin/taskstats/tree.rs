@@ -0,0 +1,101 @@
+use crate::cmd::{print_stats, printer, Output, PrintConfig};
+use linux_taskstats::format::HeaderFormat;
+use linux_taskstats::tree::ProcessTree;
+use linux_taskstats::{Client, Error, NetlinkError};
+use log::warn;
+
+use std::io;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for exit records before checking if the command has exited
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct Config<H: HeaderFormat> {
+    pub command: Vec<String>,
+    pub print: PrintConfig,
+    pub header_format: H,
+}
+
+/// Cpumask covering all configured CPUs, to listen exit of tasks wherever they run
+pub fn all_cpus_mask() -> String {
+    let ncpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(1);
+    format!("0-{}", ncpus - 1)
+}
+
+pub fn tree_main<H: HeaderFormat>(config: Config<H>) {
+    env_logger::init();
+
+    let client = Client::open().expect("netlink init");
+    let cpu_mask = all_cpus_mask();
+    client
+        .register_cpumask(&cpu_mask)
+        .expect("register cpumask");
+
+    let spawned = process::Command::new(&config.command[0])
+        .args(&config.command[1..])
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            deregister(&client, &cpu_mask);
+            panic!("spawn command: {}", e);
+        }
+    };
+    let mut tree = ProcessTree::new(child.id())
+        .with_parent(process::id())
+        .with_procfs(Arc::clone(&config.print.procfs));
+    client
+        .set_rx_timeout(Some(EXIT_POLL_INTERVAL))
+        .expect("set receive timeout");
+    // Records lost by overruns may include the root's, so the command is
+    // also waited for and listening goes on only while records keep coming
+    let mut status = None;
+    while !tree.is_finished() {
+        if status.is_none() {
+            status = child.try_wait().expect("wait command");
+        }
+        match client.listen_registered() {
+            Ok(records) => records.into_iter().for_each(|ts| tree.push(ts)),
+            Err(Error::Netlink(NetlinkError::SocketIo(e)))
+                if e.kind() == io::ErrorKind::WouldBlock =>
+            {
+                if status.is_some() {
+                    warn!("Exit record of the command has not arrived");
+                    break;
+                }
+            }
+            Err(e) => warn!("Failed to receive exit records: {}", e),
+        }
+    }
+    let status = match status {
+        Some(status) => status,
+        None => child.wait().expect("wait command"),
+    };
+    deregister(&client, &cpu_mask);
+
+    let stats = tree.finish();
+    // Sorting and filtering apply to tasks, never dropping nor moving the total
+    let printer = printer(config.header_format, &config.print);
+    let mut records: Vec<_> = printer.select(&stats.tasks).into_iter().copied().collect();
+    let printer = printer.without_selection();
+    if let Output::Table = config.print.output {
+        print_stats(&printer, &config.print, &records);
+        println!();
+        println!("Total of {} tasks:", stats.tasks.len());
+        print_stats(&printer, &config.print, &[stats.total]);
+    } else {
+        // Machine readable outputs get the total as the last record, of TID 0
+        records.push(stats.total);
+        print_stats(&printer, &config.print, &records);
+    }
+
+    process::exit(status.code().unwrap_or(1));
+}
+
+fn deregister(client: &Client, cpu_mask: &str) {
+    client
+        .deregister_cpumask(cpu_mask)
+        .expect("deregister cpumask");
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Name of the file listing IDs of processes in a cgroup
+const PROCS_FILE: &str = "cgroup.procs";
+
+/// Return the IDs of processes in the cgroup at `path`, a directory in the
+/// cgroup filesystem such as `/sys/fs/cgroup/system.slice/nginx.service`, and
+/// also in its descendant cgroups if `recursive`.
+///
+/// IDs are sorted and deduplicated, as a process can be listed in multiple
+/// cgroups of a cgroup v1 hierarchy when its threads are in different ones.
+/// Descendant cgroups removed while walking them are skipped.
+pub fn procs<P: AsRef<Path>>(path: P, recursive: bool) -> io::Result<Vec<u32>> {
+    let mut pids = BTreeSet::new();
+    read_procs(path.as_ref(), recursive, &mut pids)?;
+    Ok(pids.into_iter().collect())
+}
+
+fn read_procs(path: &Path, recursive: bool, pids: &mut BTreeSet<u32>) -> io::Result<()> {
+    let content = fs::read_to_string(path.join(PROCS_FILE))?;
+    for line in content.lines() {
+        let pid = line.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid pid in {}: {}", path.display(), line),
+            )
+        })?;
+        pids.insert(pid);
+    }
+    if !recursive {
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        match read_procs(&entry.path(), recursive, pids) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "linux-taskstats-cgroup-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn cgroup(path: &Path, procs: &str) {
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join(PROCS_FILE), procs).unwrap();
+    }
+
+    #[test]
+    fn test_procs() {
+        let root = temp_dir("procs");
+        cgroup(&root, "30\n10\n");
+        cgroup(&root.join("a"), "");
+        cgroup(&root.join("a/b"), "20\n10\n");
+        cgroup(&root.join("c"), "40\n");
+        fs::write(root.join("cpu.max"), "max 100000\n").unwrap();
+
+        assert_eq!(vec![10, 30], procs(&root, false).unwrap());
+        assert_eq!(vec![10, 20, 30, 40], procs(&root, true).unwrap());
+        assert_eq!(vec![10, 20], procs(root.join("a"), true).unwrap());
+        assert!(procs(root.join("d"), true).is_err());
+
+        fs::write(root.join("c").join(PROCS_FILE), "x\n").unwrap();
+        let err = procs(&root, true).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
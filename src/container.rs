@@ -0,0 +1,317 @@
+use crate::procfs::{self, ProcFs};
+use crate::TaskStats;
+use std::sync::Arc;
+
+/// Stats of a task, possibly along with more about the task, taken by
+/// printers and exporters.
+pub trait Record {
+    fn stats(&self) -> &TaskStats;
+
+    /// Return the container and PID namespace of the task if resolved.
+    fn container(&self) -> Option<&ContainerInfo> {
+        None
+    }
+}
+
+impl Record for TaskStats {
+    fn stats(&self) -> &TaskStats {
+        self
+    }
+}
+
+/// Stats of a task along with its container and PID namespace, resolved by
+/// `Enricher` when the stats arrived.
+///
+/// Printers and exporters fall back to reading /proc at the time of output
+/// for records without the container.
+#[derive(Debug, Clone)]
+pub struct Enriched {
+    pub stats: TaskStats,
+    pub container: Option<ContainerInfo>,
+}
+
+impl From<TaskStats> for Enriched {
+    fn from(stats: TaskStats) -> Self {
+        Self {
+            stats,
+            container: None,
+        }
+    }
+}
+
+impl Record for Enriched {
+    fn stats(&self) -> &TaskStats {
+        &self.stats
+    }
+
+    fn container(&self) -> Option<&ContainerInfo> {
+        self.container.as_ref()
+    }
+}
+
+/// Container runtime told by the cgroup path of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Runtime {
+    Docker,
+    Containerd,
+    Podman,
+    CriO,
+}
+
+impl Runtime {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Runtime::Docker => "docker",
+            Runtime::Containerd => "containerd",
+            Runtime::Podman => "podman",
+            Runtime::CriO => "cri-o",
+        }
+    }
+}
+
+/// Container and PID namespace of a task.
+///
+/// Fields which couldn't be resolved, e.g. of a task outside containers or
+/// of a task already gone, are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContainerInfo {
+    /// Path of the cgroup, in the cgroup v2 hierarchy if any
+    pub cgroup: Option<String>,
+    /// Runtime of the container, which is unknown for some cgroup layouts
+    /// even when `container_id` is found
+    pub runtime: Option<Runtime>,
+    /// Full ID of the container, 64 hex digits
+    pub container_id: Option<String>,
+    /// UID of the Kubernetes pod
+    pub pod_uid: Option<String>,
+    /// Inode number of the PID namespace
+    pub pid_ns: Option<u64>,
+    /// ID of the task in its own PID namespace, the last one of `NSpid`
+    pub ns_pid: Option<u32>,
+}
+
+impl ContainerInfo {
+    /// Take the container ID and the pod UID out of a cgroup path, e.g.
+    /// * `/system.slice/docker-<id>.scope` or `/docker/<id>` of Docker
+    /// * `/machine.slice/libpod-<id>.scope/container` of Podman
+    /// * `/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod<uid>.slice/cri-containerd-<id>.scope`
+    ///   or `/kubepods/burstable/pod<uid>/<id>` of Kubernetes
+    ///
+    /// Monitors of containers such as `libpod-conmon-<id>.scope` aren't in the containers.
+    pub fn from_cgroup_path(path: &str) -> Self {
+        let mut info = ContainerInfo {
+            cgroup: Some(path.to_string()),
+            ..Default::default()
+        };
+        let segments: Vec<_> = path.split('/').filter(|s| !s.is_empty()).collect();
+        for (i, segment) in segments.iter().enumerate().rev() {
+            if info.container_id.is_none() {
+                let parent = i.checked_sub(1).map(|p| segments[p]);
+                if let Some((runtime, id)) = parse_container_segment(segment, parent) {
+                    info.runtime = runtime;
+                    info.container_id = Some(id.to_string());
+                }
+            }
+            if info.pod_uid.is_none() {
+                info.pod_uid = parse_pod_segment(segment);
+            }
+        }
+        info
+    }
+
+    /// Return the container ID shortened to 12 characters as `docker ps` does.
+    pub fn short_id(&self) -> Option<&str> {
+        self.container_id.as_ref().map(|id| &id[..12])
+    }
+}
+
+/// Find a container ID, 64 hex digits, in a segment of a cgroup path along
+/// with the runtime told by its prefix or by `parent`
+fn parse_container_segment<'a>(
+    segment: &'a str,
+    parent: Option<&str>,
+) -> Option<(Option<Runtime>, &'a str)> {
+    let segment = segment.strip_suffix(".scope").unwrap_or(segment);
+    let (prefix, id) = match segment.rfind('-') {
+        Some(i) => (&segment[..i], &segment[i + 1..]),
+        None => ("", segment),
+    };
+    if id.len() != 64 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let runtime = match prefix {
+        "docker" => Some(Runtime::Docker),
+        "cri-containerd" => Some(Runtime::Containerd),
+        "libpod" => Some(Runtime::Podman),
+        "crio" => Some(Runtime::CriO),
+        "" if parent == Some("docker") => Some(Runtime::Docker),
+        _ if prefix.ends_with("conmon") => return None,
+        _ => None,
+    };
+    Some((runtime, id))
+}
+
+/// Find a pod UID in a segment of a cgroup path, `pod<uid>` or
+/// `kubepods-<qos>-pod<uid>.slice` with dashes of the UID escaped to underscores
+fn parse_pod_segment(segment: &str) -> Option<String> {
+    let segment = segment.strip_suffix(".slice").unwrap_or(segment);
+    let uid = match segment.rfind("-pod") {
+        Some(i) => &segment[i + 4..],
+        None => segment.strip_prefix("pod")?,
+    };
+    let uid = uid.replace('_', "-");
+    if uid.len() >= 32 && uid.bytes().all(|b| b.is_ascii_hexdigit() || b == b'-') {
+        Some(uid)
+    } else {
+        None
+    }
+}
+
+/// Resolves containers and PID namespaces of tasks out of /proc, to enrich
+/// exit records and samples as they arrive.
+///
+/// Resolution is best effort: an exit record may arrive after its task has
+/// been reaped, leaving nothing to resolve.
+#[derive(Clone)]
+pub struct Enricher {
+    procfs: Arc<dyn ProcFs>,
+}
+
+impl Default for Enricher {
+    fn default() -> Self {
+        Self {
+            procfs: procfs::system(),
+        }
+    }
+}
+
+impl Enricher {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Read tasks through `procfs` instead of /proc, e.g. the host's proc
+    /// mounted in a container.
+    pub fn with_procfs(mut self, procfs: Arc<dyn ProcFs>) -> Self {
+        self.procfs = procfs;
+        self
+    }
+
+    /// Resolve the container and PID namespace of the task `tid`.
+    pub fn resolve(&self, tid: u32) -> ContainerInfo {
+        let mut info = self
+            .procfs
+            .cgroup(tid)
+            .map(|path| ContainerInfo::from_cgroup_path(&path))
+            .unwrap_or_default();
+        info.ns_pid = self.procfs.ns_pid(tid);
+        info.pid_ns = self.procfs.pid_ns(tid);
+        info
+    }
+
+    /// Attach the container and PID namespace of a task to its stats.
+    pub fn enrich(&self, ts: &TaskStats) -> Enriched {
+        Enriched {
+            stats: *ts,
+            container: Some(self.resolve(ts.tid)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const ID: &str = "4f2a3c9b1d0e4f2a3c9b1d0e4f2a3c9b1d0e4f2a3c9b1d0e4f2a3c9b1d0e4f2a";
+    const POD_UID: &str = "7c2b1a3e-5d4f-4e6a-9b8c-1d2e3f4a5b6c";
+
+    fn fixture() -> Enricher {
+        Enricher::new().with_procfs(Arc::new(procfs::tests::fixture()))
+    }
+
+    #[test]
+    fn test_from_cgroup_path() {
+        let docker = ContainerInfo::from_cgroup_path(&format!("/system.slice/docker-{}.scope", ID));
+        assert_eq!(Some(Runtime::Docker), docker.runtime);
+        assert_eq!(Some(ID), docker.container_id.as_deref());
+        assert_eq!(Some(&ID[..12]), docker.short_id());
+        assert_eq!(None, docker.pod_uid);
+
+        let docker_v1 = ContainerInfo::from_cgroup_path(&format!("/docker/{}", ID));
+        assert_eq!(Some(Runtime::Docker), docker_v1.runtime);
+
+        let podman = ContainerInfo::from_cgroup_path(&format!(
+            "/machine.slice/libpod-{}.scope/container",
+            ID
+        ));
+        assert_eq!(Some(Runtime::Podman), podman.runtime);
+        assert_eq!(Some(ID), podman.container_id.as_deref());
+        let conmon =
+            ContainerInfo::from_cgroup_path(&format!("/machine.slice/libpod-conmon-{}.scope", ID));
+        assert_eq!(None, conmon.container_id);
+
+        let k8s = ContainerInfo::from_cgroup_path(&format!(
+            "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod{}.slice/cri-containerd-{}.scope",
+            POD_UID.replace('-', "_"),
+            ID
+        ));
+        assert_eq!(Some(Runtime::Containerd), k8s.runtime);
+        assert_eq!(Some(ID), k8s.container_id.as_deref());
+        assert_eq!(Some(POD_UID), k8s.pod_uid.as_deref());
+
+        let k8s_cgroupfs =
+            ContainerInfo::from_cgroup_path(&format!("/kubepods/besteffort/pod{}/{}", POD_UID, ID));
+        assert_eq!(None, k8s_cgroupfs.runtime);
+        assert_eq!(Some(ID), k8s_cgroupfs.container_id.as_deref());
+        assert_eq!(Some(POD_UID), k8s_cgroupfs.pod_uid.as_deref());
+
+        let host = ContainerInfo::from_cgroup_path("/user.slice/user-1000.slice/session-1.scope");
+        assert_eq!(None, host.container_id);
+        assert_eq!(None, host.pod_uid);
+        assert_eq!(
+            Some("/user.slice/user-1000.slice/session-1.scope"),
+            host.cgroup.as_deref()
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let enricher = fixture();
+
+        let docker = enricher.resolve(1234);
+        assert_eq!(Some(Runtime::Docker), docker.runtime);
+        assert_eq!(Some(ID), docker.container_id.as_deref());
+        assert_eq!(Some(4026532448), docker.pid_ns);
+        assert_eq!(Some(1), docker.ns_pid);
+
+        let k8s = enricher.resolve(2345);
+        assert_eq!(Some(Runtime::Containerd), k8s.runtime);
+        assert_eq!(Some(POD_UID), k8s.pod_uid.as_deref());
+        assert_eq!(Some(7), k8s.ns_pid);
+
+        let podman_v1 = enricher.resolve(3456);
+        assert_eq!(Some(Runtime::Podman), podman_v1.runtime);
+        assert_eq!(Some(4026532601), podman_v1.pid_ns);
+
+        let host = enricher.resolve(1);
+        assert_eq!(Some("/init.scope"), host.cgroup.as_deref());
+        assert_eq!(None, host.container_id);
+        assert_eq!(Some(4026531836), host.pid_ns);
+        assert_eq!(Some(1), host.ns_pid);
+
+        assert_eq!(ContainerInfo::default(), enricher.resolve(99999));
+    }
+
+    #[test]
+    fn test_enrich() {
+        let ts = TaskStats::builder().with_tid(2345).build();
+
+        let enriched = fixture().enrich(&ts);
+        assert_eq!(2345, enriched.stats().tid);
+        assert_eq!(
+            Some(POD_UID),
+            enriched.container().unwrap().pod_uid.as_deref()
+        );
+        assert!(Enriched::from(ts).container().is_none());
+        assert!(ts.container().is_none());
+    }
+}
@@ -0,0 +1,400 @@
+use crate::c_headers::{
+    TASKSTATS_CMD_ATTR_DEREGISTER_CPUMASK, TASKSTATS_CMD_ATTR_PID,
+    TASKSTATS_CMD_ATTR_REGISTER_CPUMASK, TASKSTATS_CMD_ATTR_TGID, TASKSTATS_CMD_GET,
+    TASKSTATS_CMD_NEW, TASKSTATS_GENL_NAME, TASKSTATS_TYPE_AGGR_PID, TASKSTATS_TYPE_AGGR_TGID,
+    TASKSTATS_TYPE_PID, TASKSTATS_TYPE_STATS, TASKSTATS_TYPE_TGID,
+};
+use crate::netlink::{nla, nlmsg, NlSocket};
+use crate::{AsBuf, Client, TaskStats};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::convert::TryInto;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Family ID of taskstats answered by `FakeKernel`
+pub const FAMILY_ID: u16 = 0x1a;
+
+/// An in-process fake of the taskstats interface of the kernel, to test code
+/// using `Client` without privileges.
+///
+/// The kernel is scripted by the stats of tasks it knows, errors to respond
+/// and exit events to deliver, and talked to by `Client` through its clones
+/// as `NlSocket`:
+///
+/// ```
+/// use linux_taskstats::fake::FakeKernel;
+/// use linux_taskstats::TaskStats;
+///
+/// let kernel = FakeKernel::new();
+/// kernel.set_pid_stats(1234, TaskStats::default());
+/// let client = kernel.client();
+/// assert!(client.pid_stats(1234).is_ok());
+/// assert!(client.pid_stats(5678).is_err());
+/// ```
+///
+/// Queries of unknown tasks are responded by `ESRCH` as the kernel does.
+/// Exit events are delivered by `Client::listen_registered` only while a
+/// cpumask is registered, and receiving with no events left fails by
+/// `WouldBlock` as a nonblocking socket does, instead of blocking forever.
+#[derive(Clone, Default)]
+pub struct FakeKernel {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    pids: HashMap<u32, TaskStats>,
+    tgids: HashMap<u32, TaskStats>,
+    /// Error numbers to respond to queries of tasks by their IDs
+    errors: HashMap<u32, i32>,
+    cpumasks: BTreeSet<String>,
+    /// Responses to requests, received before events
+    responses: VecDeque<Vec<u8>>,
+    events: VecDeque<Event>,
+}
+
+enum Event {
+    Exit(Vec<u8>),
+    Overrun,
+}
+
+impl FakeKernel {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create a `Client` talking to this kernel.
+    pub fn client(&self) -> Client<FakeKernel> {
+        Client::with_socket(self.clone(), ()).expect("family lookup on fake kernel")
+    }
+
+    /// Answer `stats` to queries of the task `tid`.
+    pub fn set_pid_stats(&self, tid: u32, stats: TaskStats) {
+        self.state().pids.insert(tid, stats);
+    }
+
+    /// Answer `stats` to queries of the thread group `tgid`.
+    pub fn set_tgid_stats(&self, tgid: u32, stats: TaskStats) {
+        self.state().tgids.insert(tgid, stats);
+    }
+
+    /// Forget the task or thread group of `id`, which has gone.
+    pub fn remove(&self, id: u32) {
+        let mut state = self.state();
+        state.pids.remove(&id);
+        state.tgids.remove(&id);
+    }
+
+    /// Respond to queries of the task or thread group of `id` by the error
+    /// number `errno`, e.g. `libc::EPERM`.
+    pub fn set_error(&self, id: u32, errno: i32) {
+        self.state().errors.insert(id, errno);
+    }
+
+    /// Stop responding errors to queries of `id`.
+    pub fn clear_error(&self, id: u32) {
+        self.state().errors.remove(&id);
+    }
+
+    /// Deliver an exit event of the task `tid` with its final `stats`, and
+    /// forget the task.
+    pub fn push_exit(&self, tid: u32, stats: TaskStats) {
+        let attrs = aggr(TASKSTATS_TYPE_AGGR_PID, TASKSTATS_TYPE_PID, tid, &stats);
+        let mut state = self.state();
+        state.pids.remove(&tid);
+        state.events.push_back(Event::Exit(message(
+            FAMILY_ID,
+            TASKSTATS_CMD_NEW as u8,
+            &attrs,
+        )));
+    }
+
+    /// Deliver an exit event of the task `tid` which is the last one of the
+    /// thread group `tgid`, along with the stats of the whole group, and
+    /// forget both.
+    pub fn push_group_exit(&self, tid: u32, stats: TaskStats, tgid: u32, group: TaskStats) {
+        let mut attrs = aggr(TASKSTATS_TYPE_AGGR_PID, TASKSTATS_TYPE_PID, tid, &stats);
+        attrs.extend(aggr(
+            TASKSTATS_TYPE_AGGR_TGID,
+            TASKSTATS_TYPE_TGID,
+            tgid,
+            &group,
+        ));
+        let mut state = self.state();
+        state.pids.remove(&tid);
+        state.tgids.remove(&tgid);
+        state.events.push_back(Event::Exit(message(
+            FAMILY_ID,
+            TASKSTATS_CMD_NEW as u8,
+            &attrs,
+        )));
+    }
+
+    /// Fail the receive at this point of events by `ENOBUFS`, as the kernel
+    /// does when exit events overflowed the receive buffer.
+    pub fn push_overrun(&self) {
+        self.state().events.push_back(Event::Overrun);
+    }
+
+    /// Return the cpumasks registered and not deregistered.
+    pub fn cpumasks(&self) -> Vec<String> {
+        self.state().cpumasks.iter().cloned().collect()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl NlSocket for FakeKernel {
+    type Addr = ();
+
+    fn send_to(&self, buf: &[u8], _addr: &()) -> io::Result<usize> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "malformed request");
+        let data_offset = nlmsg::HDRLEN + nlmsg::GENL_HDRLEN + nla::HDRLEN;
+        if buf.len() < data_offset {
+            return Err(invalid());
+        }
+        let header: libc::nlmsghdr = unsafe { ptr::read_unaligned(buf.as_ptr() as *const _) };
+        let genl: libc::genlmsghdr =
+            unsafe { ptr::read_unaligned(buf.as_ptr().add(nlmsg::HDRLEN) as *const _) };
+        let attr: libc::nlattr = unsafe {
+            ptr::read_unaligned(buf.as_ptr().add(nlmsg::HDRLEN + nlmsg::GENL_HDRLEN) as *const _)
+        };
+        let data = &buf[data_offset..];
+
+        let mut state = self.state();
+        let response = match (header.nlmsg_type, genl.cmd as i32, attr.nla_type as u32) {
+            (CTRL, libc::CTRL_CMD_GETFAMILY, _) if data.starts_with(TASKSTATS_GENL_NAME) => {
+                let mut attrs = nlattr(libc::CTRL_ATTR_FAMILY_NAME as u16, TASKSTATS_GENL_NAME);
+                attrs.extend(nlattr(libc::CTRL_ATTR_FAMILY_ID as u16, FAMILY_ID.as_buf()));
+                Some(message(CTRL, libc::CTRL_CMD_NEWFAMILY as u8, &attrs))
+            }
+            (CTRL, _, _) => Some(error(&header, libc::ENOENT)),
+            (FAMILY_ID, GET, kind @ (TASKSTATS_CMD_ATTR_PID | TASKSTATS_CMD_ATTR_TGID)) => {
+                let id = u32::from_ne_bytes(data.get(..4).ok_or_else(invalid)?.try_into().unwrap());
+                let (tasks, aggr_type, id_type) = if kind == TASKSTATS_CMD_ATTR_PID {
+                    (&state.pids, TASKSTATS_TYPE_AGGR_PID, TASKSTATS_TYPE_PID)
+                } else {
+                    (&state.tgids, TASKSTATS_TYPE_AGGR_TGID, TASKSTATS_TYPE_TGID)
+                };
+                Some(match (state.errors.get(&id), tasks.get(&id)) {
+                    (Some(errno), _) => error(&header, *errno),
+                    (None, Some(stats)) => message(
+                        FAMILY_ID,
+                        TASKSTATS_CMD_NEW as u8,
+                        &aggr(aggr_type, id_type, id, stats),
+                    ),
+                    (None, None) => error(&header, libc::ESRCH),
+                })
+            }
+            (FAMILY_ID, GET, TASKSTATS_CMD_ATTR_REGISTER_CPUMASK) => {
+                state.cpumasks.insert(cpumask(data));
+                None
+            }
+            (FAMILY_ID, GET, TASKSTATS_CMD_ATTR_DEREGISTER_CPUMASK) => {
+                state.cpumasks.remove(&cpumask(data));
+                None
+            }
+            _ => Some(error(&header, libc::EINVAL)),
+        };
+        state.responses.extend(response);
+        Ok(buf.len())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state();
+        let msg = match state.responses.pop_front() {
+            Some(msg) => msg,
+            None if state.cpumasks.is_empty() => return Err(io::ErrorKind::WouldBlock.into()),
+            None => match state.events.pop_front() {
+                Some(Event::Exit(msg)) => msg,
+                Some(Event::Overrun) => return Err(io::Error::from_raw_os_error(libc::ENOBUFS)),
+                None => return Err(io::ErrorKind::WouldBlock.into()),
+            },
+        };
+        let len = msg.len().min(buf.len());
+        buf[..len].copy_from_slice(&msg[..len]);
+        Ok(len)
+    }
+}
+
+const CTRL: u16 = libc::GENL_ID_CTRL as u16;
+const GET: i32 = TASKSTATS_CMD_GET as i32;
+
+fn cpumask(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// Encode an attribute padded to the alignment
+fn nlattr(nla_type: u16, payload: &[u8]) -> Vec<u8> {
+    let header = libc::nlattr {
+        nla_len: (nla::HDRLEN + payload.len()) as u16,
+        nla_type,
+    };
+    let mut buf = header.as_buf().to_vec();
+    buf.extend_from_slice(payload);
+    buf.resize(nla::align(buf.len()), 0);
+    buf
+}
+
+/// Encode a nested attribute of the ID and stats of a task or thread group
+fn aggr(aggr_type: u32, id_type: u32, id: u32, stats: &TaskStats) -> Vec<u8> {
+    let mut nested = nlattr(id_type as u16, id.as_buf());
+    nested.extend(nlattr(TASKSTATS_TYPE_STATS as u16, stats.inner().as_buf()));
+    nlattr(aggr_type as u16, &nested)
+}
+
+/// Encode a generic netlink message carrying `attrs`
+fn message(nlmsg_type: u16, cmd: u8, attrs: &[u8]) -> Vec<u8> {
+    let len = nlmsg::HDRLEN + nlmsg::GENL_HDRLEN + attrs.len();
+    let header = libc::nlmsghdr {
+        nlmsg_len: len as u32,
+        nlmsg_type,
+        nlmsg_flags: 0,
+        nlmsg_seq: 0,
+        nlmsg_pid: 0,
+    };
+    let genl = libc::genlmsghdr {
+        cmd,
+        version: 0x1,
+        reserved: 0,
+    };
+    let mut buf = Vec::with_capacity(len);
+    buf.extend_from_slice(header.as_buf());
+    buf.extend_from_slice(genl.as_buf());
+    buf.extend_from_slice(attrs);
+    buf
+}
+
+/// Encode an error response to the request of `request`
+fn error(request: &libc::nlmsghdr, errno: i32) -> Vec<u8> {
+    let len = nlmsg::HDRLEN + mem::size_of::<libc::nlmsgerr>();
+    let header = libc::nlmsghdr {
+        nlmsg_len: len as u32,
+        nlmsg_type: libc::NLMSG_ERROR as u16,
+        nlmsg_flags: 0,
+        nlmsg_seq: request.nlmsg_seq,
+        nlmsg_pid: request.nlmsg_pid,
+    };
+    let err = libc::nlmsgerr {
+        error: -errno,
+        msg: *request,
+    };
+    let mut buf = Vec::with_capacity(len);
+    buf.extend_from_slice(header.as_buf());
+    buf.extend_from_slice(err.as_buf());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{netlink, Cpu, Error};
+    use std::time::Duration;
+
+    fn stats(tid: u32, utime: u64) -> TaskStats {
+        TaskStats::builder()
+            .with_tid(tid)
+            .with_cpu(Cpu {
+                utime_total: Duration::from_micros(utime),
+                ..Default::default()
+            })
+            .build()
+    }
+
+    fn errno<T>(result: crate::Result<T>) -> Option<i32> {
+        match result {
+            Err(Error::Netlink(netlink::Error::ErrorResponse(errno))) => Some(errno),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let kernel = FakeKernel::new();
+        kernel.set_pid_stats(10, stats(10, 1000));
+        kernel.set_tgid_stats(10, stats(0, 3000));
+        let client = kernel.client();
+
+        let ts = client.pid_stats(10).unwrap();
+        assert_eq!(10, ts.tid);
+        assert_eq!(Duration::from_micros(1000), ts.cpu.utime_total);
+        let ts = client.tgid_stats(10).unwrap();
+        assert_eq!(Duration::from_micros(3000), ts.cpu.utime_total);
+        assert_eq!(Some(libc::ESRCH), errno(client.pid_stats(11)));
+
+        kernel.set_error(10, libc::EPERM);
+        assert_eq!(Some(libc::EPERM), errno(client.pid_stats(10)));
+        assert_eq!(Some(libc::EPERM), errno(client.tgid_stats(10)));
+        kernel.clear_error(10);
+        assert!(client.pid_stats(10).is_ok());
+
+        kernel.remove(10);
+        assert_eq!(Some(libc::ESRCH), errno(client.pid_stats(10)));
+    }
+
+    #[test]
+    fn test_exits() {
+        let kernel = FakeKernel::new();
+        let client = kernel.client();
+        kernel.set_pid_stats(10, stats(10, 1000));
+        kernel.push_exit(10, stats(10, 2000));
+        kernel.push_overrun();
+        kernel.push_group_exit(11, stats(11, 1000), 11, stats(0, 3000));
+
+        // Events aren't delivered until registered
+        assert!(matches!(
+            client.listen_registered(),
+            Err(Error::Netlink(netlink::Error::SocketIo(e))) if e.kind() == io::ErrorKind::WouldBlock
+        ));
+        client.register_cpumask("0-3").unwrap();
+        assert_eq!(vec!["0-3"], kernel.cpumasks());
+
+        let exits = client.listen_registered().unwrap();
+        assert_eq!(1, exits.len());
+        assert_eq!(Duration::from_micros(2000), exits[0].cpu.utime_total);
+        assert_eq!(Some(libc::ESRCH), errno(client.pid_stats(10)));
+
+        assert!(matches!(
+            client.listen_registered(),
+            Err(Error::Netlink(netlink::Error::SocketIo(e))) if e.raw_os_error() == Some(libc::ENOBUFS)
+        ));
+
+        let exits = client.listen_registered().unwrap();
+        assert_eq!(
+            vec![11, 0],
+            exits.iter().map(|ts| ts.tid).collect::<Vec<_>>()
+        );
+        assert_eq!(Duration::from_micros(3000), exits[1].cpu.utime_total);
+
+        client.deregister_cpumask("0-3").unwrap();
+        assert!(kernel.cpumasks().is_empty());
+    }
+
+    #[test]
+    fn test_cgroup_task_stats() {
+        let dir = std::env::temp_dir().join(format!("linux-taskstats-fake-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("cgroup.procs"), "10\n20\n30\n").unwrap();
+
+        let kernel = FakeKernel::new();
+        kernel.set_tgid_stats(10, stats(10, 1000));
+        kernel.set_tgid_stats(30, stats(30, 2000));
+        let group = kernel.client().cgroup_task_stats(&dir, false).unwrap();
+        assert_eq!(2, group.tasks);
+        assert_eq!(Duration::from_micros(3000), group.stats.cpu.utime_total);
+
+        kernel.set_error(30, libc::ESRCH);
+        let group = kernel.client().cgroup_task_stats(&dir, false).unwrap();
+        assert_eq!(1, group.tasks);
+
+        kernel.set_error(30, libc::EPERM);
+        let result = kernel.client().cgroup_task_stats(&dir, false);
+        assert_eq!(Some(libc::EPERM), errno(result));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
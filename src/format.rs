@@ -1,9 +1,45 @@
+mod column;
+mod header;
+pub mod prometheus;
+pub mod statsd;
+mod template;
+mod unit;
+
+use crate::container::Record;
+use crate::procfs::{self, ProcFs};
+use crate::report::Bucket;
 use crate::TaskStats;
-use prettytable::{self as ptable, row};
+pub use column::{Column, Unit, Value};
+pub use header::{
+    CgroupHeaderFormat, CmdlineHeaderFormat, CommHeaderFormat, ContainerHeaderFormat,
+    UserHeaderFormat,
+};
+use prettytable as ptable;
 use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+pub use template::{Field, Part, Template, UnknownColumn};
+pub use unit::UnitStyle;
 
+/// Formats the label of a task printed along with its stats.
 pub trait HeaderFormat {
-    fn format(&self, tid: u32) -> String;
+    fn format(&self, ts: &TaskStats) -> String;
+
+    /// Format the label of a record, which may tell more about the task than
+    /// its stats do. Defaults to `format` of the stats.
+    fn format_record(&self, record: &dyn Record) -> String {
+        self.format(record.stats())
+    }
+}
+
+impl<T: HeaderFormat + ?Sized> HeaderFormat for Box<T> {
+    fn format(&self, ts: &TaskStats) -> String {
+        (**self).format(ts)
+    }
+
+    fn format_record(&self, record: &dyn Record) -> String {
+        (**self).format_record(record)
+    }
 }
 
 #[derive(Default)]
@@ -16,226 +52,640 @@ impl DefaultHeaderFormat {
 }
 
 impl HeaderFormat for DefaultHeaderFormat {
-    fn format(&self, tid: u32) -> String {
-        format!("TID: {}", tid)
+    fn format(&self, ts: &TaskStats) -> String {
+        format!("TID: {}", ts.tid)
+    }
+}
+
+type FieldValue = fn(&TaskStats) -> u128;
+
+/// Keys and values of the JSON output, in the order of output.
+const JSON_FIELDS: &[(&str, FieldValue)] = &[
+    ("tid", |ts| ts.tid as u128),
+    ("utime_us", |ts| ts.cpu.utime_total.as_micros()),
+    ("stime_us", |ts| ts.cpu.stime_total.as_micros()),
+    ("real_time_ns", |ts| ts.cpu.real_time_total.as_nanos()),
+    ("virtual_time_ns", |ts| ts.cpu.virtual_time_total.as_nanos()),
+    ("rss_total_mb_us", |ts| ts.memory.rss_total as u128),
+    ("virt_total_mb_us", |ts| ts.memory.virt_total as u128),
+    ("minor_faults", |ts| ts.memory.minor_faults as u128),
+    ("major_faults", |ts| ts.memory.major_faults as u128),
+    ("read_bytes", |ts| ts.io.read_bytes as u128),
+    ("write_bytes", |ts| ts.io.write_bytes as u128),
+    ("read_syscalls", |ts| ts.io.read_syscalls as u128),
+    ("write_syscalls", |ts| ts.io.write_syscalls as u128),
+    ("blkio_read_bytes", |ts| ts.blkio.read_bytes as u128),
+    ("blkio_write_bytes", |ts| ts.blkio.write_bytes as u128),
+    ("blkio_cancelled_write_bytes", |ts| {
+        ts.blkio.cancelled_write_bytes as u128
+    }),
+    ("voluntary_ctx_switches", |ts| {
+        ts.ctx_switches.voluntary as u128
+    }),
+    ("non_voluntary_ctx_switches", |ts| {
+        ts.ctx_switches.non_voluntary as u128
+    }),
+    ("cpu_delay_count", |ts| ts.delays.cpu.count as u128),
+    ("cpu_delay_ns", |ts| ts.delays.cpu.delay_total.as_nanos()),
+    ("blkio_delay_count", |ts| ts.delays.blkio.count as u128),
+    ("blkio_delay_ns", |ts| {
+        ts.delays.blkio.delay_total.as_nanos()
+    }),
+    ("swapin_delay_count", |ts| ts.delays.swapin.count as u128),
+    ("swapin_delay_ns", |ts| {
+        ts.delays.swapin.delay_total.as_nanos()
+    }),
+    ("freepages_delay_count", |ts| {
+        ts.delays.freepages.count as u128
+    }),
+    ("freepages_delay_ns", |ts| {
+        ts.delays.freepages.delay_total.as_nanos()
+    }),
+];
+
+/// Quote a text field for CSV, or replace special characters for TSV which
+/// has no way of quoting.
+fn escape_field(field: &str, delimiter: char) -> String {
+    let special = |c: char| c == delimiter || c == '"' || c == '\n' || c == '\r';
+    if !field.contains(special) {
+        return field.to_string();
+    }
+    if delimiter == '\t' {
+        field.replace(special, " ")
+    } else {
+        format!("\"{}\"", field.replace('"', "\"\""))
     }
 }
 
+fn write_json_object<W: Write>(out: &mut W, record: &dyn Record) -> io::Result<()> {
+    write!(out, "{{")?;
+    for (i, (key, value)) in JSON_FIELDS.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "\"{}\":{}", key, value(record.stats()))?;
+    }
+    if let Some(container) = record.container() {
+        let string = |s: &Option<String>| s.as_deref().map_or("null".to_string(), json_string);
+        let number = |n: Option<u64>| n.map_or("null".to_string(), |n| n.to_string());
+        write!(
+            out,
+            ",\"cgroup\":{},\"container_runtime\":{},\"container_id\":{},\"pod_uid\":{},\"pid_ns\":{},\"ns_pid\":{}",
+            string(&container.cgroup),
+            string(&container.runtime.map(|r| r.name().to_string())),
+            string(&container.container_id),
+            string(&container.pod_uid),
+            number(container.pid_ns),
+            number(container.ns_pid.map(u64::from)),
+        )?;
+    }
+    write!(out, "}}")
+}
+
+/// Quote a string as a JSON string
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Order of sorting tasks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
 pub struct Printer<H: HeaderFormat> {
     header_format: H,
+    unit_style: UnitStyle,
+    precision: usize,
+    sort: Option<(&'static Column, Order)>,
+    top: Option<usize>,
+    thresholds: Vec<(&'static Column, Value)>,
+    procfs: Arc<dyn ProcFs>,
 }
 
 impl<H: HeaderFormat> Printer<H> {
     pub fn new(header_format: H) -> Self {
-        Self { header_format }
+        Self {
+            header_format,
+            unit_style: UnitStyle::default(),
+            precision: 1,
+            sort: None,
+            top: None,
+            thresholds: Vec::new(),
+            procfs: procfs::system(),
+        }
+    }
+
+    /// Sort tasks by the value of `column` in all outputs.
+    pub fn with_sort(mut self, column: &'static Column, order: Order) -> Self {
+        self.sort = Some((column, order));
+        self
+    }
+
+    /// Print only the first `n` tasks, after filtering and sorting, in all outputs.
+    pub fn with_top(mut self, n: usize) -> Self {
+        self.top = Some(n);
+        self
+    }
+
+    /// Hide tasks having the value of `column` less than `min`, in the raw
+    /// unit of the column (see `Column::parse_value`), from all outputs.
+    ///
+    /// Can be called multiple times to hide tasks below any of thresholds.
+    /// Tasks with a text value in `column` are never hidden.
+    pub fn with_min(mut self, column: &'static Column, min: Value) -> Self {
+        self.thresholds.push((column, min));
+        self
+    }
+
+    /// Read tasks through `procfs` instead of /proc, for the `tgid` tag of
+    /// `print_influx` on kernels not reporting it. Header formats read their own.
+    pub fn with_procfs(mut self, procfs: Arc<dyn ProcFs>) -> Self {
+        self.procfs = procfs;
+        self
+    }
+
+    /// Drop sorting, top-N and thresholds, e.g. to print records selected by
+    /// `select` along with ones which must be printed as they are.
+    pub fn without_selection(mut self) -> Self {
+        self.sort = None;
+        self.top = None;
+        self.thresholds.clear();
+        self
+    }
+
+    /// Apply thresholds, sorting and top-N on `stats`, as all outputs do.
+    pub fn select<'a, R: Record>(&self, stats: &'a [R]) -> Vec<&'a R> {
+        let mut selected: Vec<_> = stats
+            .iter()
+            .filter(|record| {
+                self.thresholds.iter().all(|(column, min)| {
+                    match (column.value(record.stats()), min) {
+                        (Value::Int(v), Value::Int(min)) => v >= *min,
+                        (Value::Int(v), Value::Float(min)) => v as f64 >= *min,
+                        (Value::Float(v), Value::Int(min)) => v >= *min as f64,
+                        (Value::Float(v), Value::Float(min)) => v >= *min,
+                        (Value::Text(_), _) | (_, Value::Text(_)) => true,
+                    }
+                })
+            })
+            .collect();
+        if let Some((column, order)) = self.sort {
+            selected.sort_by_cached_key(|record| column.value(record.stats()));
+            if order == Order::Descending {
+                selected.reverse();
+            }
+        }
+        if let Some(n) = self.top {
+            selected.truncate(n);
+        }
+        selected
+    }
+
+    /// Set the style of rendering values in tables and CSV/TSV outputs.
+    ///
+    /// JSON outputs and `print_full` always print raw values.
+    pub fn with_unit_style(mut self, unit_style: UnitStyle) -> Self {
+        self.unit_style = unit_style;
+        self
+    }
+
+    /// Set the number of fractional digits of values rendered in styles other
+    /// than `UnitStyle::Raw`. Defaults to 1.
+    pub fn with_precision(mut self, precision: usize) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    fn render_field(&self, field: &Field, record: &dyn Record, unit_style: UnitStyle) -> String {
+        match field {
+            Field::Task => self.header_format.format_record(record),
+            Field::Column(c) => unit::render(c, record.stats(), unit_style, self.precision),
+        }
+    }
+
+    pub fn print_summary_lines<W: Write, R: Record>(
+        &self,
+        out: &mut W,
+        stats: &[R],
+    ) -> io::Result<()> {
+        self.print_template_table(out, stats, &Template::summary())
+    }
+
+    pub fn print_delay_lines<W: Write, R: Record>(
+        &self,
+        out: &mut W,
+        stats: &[R],
+    ) -> io::Result<()> {
+        self.print_template_table(out, stats, &Template::delay())
     }
 
-    pub fn print_summary_lines<W: Write>(
+    /// Print a table of the task label and the given columns, a row per task.
+    pub fn print_table<W: Write, R: Record>(
         &self,
         out: &mut W,
-        stats: &[TaskStats],
+        stats: &[R],
+        columns: &[&Column],
+    ) -> io::Result<()> {
+        let names: Vec<_> = columns.iter().map(|c| c.name).collect();
+        let template = Template::from_fields(&format!("task,{}", names.join(",")))
+            .expect("registered columns");
+        self.print_template_table(out, stats, &template)
+    }
+
+    /// Print a table having the fields of `template` as columns, a row per task.
+    pub fn print_template_table<W: Write, R: Record>(
+        &self,
+        out: &mut W,
+        stats: &[R],
+        template: &Template,
     ) -> io::Result<()> {
         let mut table = ptable::Table::new();
         table.set_format(*ptable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-        table.add_row(row![
-            c =>
-            "Task",
-            "utime",
-            "stime",
-            "rss",
-            "vmem",
-            "read",
-            "write",
-            "d:cpu",
-            "d:bio",
-            "d:swap",
-            "d:reclaim"
-        ]);
-        for ts in stats {
-            table.add_row(row![
-                l->self.header_format.format(ts.tid),
-                r->ts.cpu.utime_total.as_micros(),
-                r->ts.cpu.stime_total.as_micros(),
-                r->ts.memory.rss_total,
-                r->ts.memory.virt_total,
-                r->ts.io.read_bytes,
-                r->ts.io.write_bytes,
-                r->ts.delays.cpu.delay_total.as_nanos(),
-                r->ts.delays.blkio.delay_total.as_nanos(),
-                r->ts.delays.swapin.delay_total.as_nanos(),
-                r->ts.delays.freepages.delay_total.as_nanos()
-            ]);
+        let header = template
+            .fields()
+            .map(|(_, title)| ptable::Cell::new(title).style_spec("c"))
+            .collect();
+        table.add_row(ptable::Row::new(header));
+        for ts in self.select(stats) {
+            let row = template
+                .fields()
+                .map(|(field, _)| {
+                    let align = match field {
+                        Field::Column(c) if !matches!(c.value(ts.stats()), Value::Text(_)) => "r",
+                        _ => "l",
+                    };
+                    ptable::Cell::new(&self.render_field(field, ts, self.unit_style))
+                        .style_spec(align)
+                })
+                .collect();
+            table.add_row(ptable::Row::new(row));
         }
         table.print(out)?;
         Ok(())
     }
 
-    pub fn print_delay_lines<W: Write>(&self, out: &mut W, stats: &[TaskStats]) -> io::Result<()> {
-        let mut table = ptable::Table::new();
-        table.set_format(*ptable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-        table.add_row(row![
-            c =>
-            "Task",
-            "cpu avg",
-            "blkio avg",
-            "swapin avg",
-            "reclaim avg",
-            "cpu total",
-            "blkio total",
-            "swapin total",
-            "reclaim total",
-        ]);
-        for ts in stats {
-            let d = ts.delays;
-            table.add_row(row![
-                l->self.header_format.format(ts.tid),
-                r->d.cpu.delay_total.as_nanos() as u64 / d.cpu.count.max(1),
-                r->d.blkio.delay_total.as_nanos() as u64 / d.blkio.count.max(1),
-                r->d.swapin.delay_total.as_nanos() as u64 / d.swapin.count.max(1),
-                r->d.freepages.delay_total.as_nanos() as u64 / d.freepages.count.max(1),
-                r->d.cpu.delay_total.as_nanos(),
-                r->d.blkio.delay_total.as_nanos(),
-                r->d.swapin.delay_total.as_nanos(),
-                r->d.freepages.delay_total.as_nanos(),
-            ]);
+    /// Print `template` for each task, with its fields replaced by values.
+    pub fn print_template_text<W: Write, R: Record>(
+        &self,
+        out: &mut W,
+        stats: &[R],
+        template: &Template,
+    ) -> io::Result<()> {
+        self.write_template_text(out, stats, template, self.unit_style)
+    }
+
+    fn write_template_text<W: Write, R: Record>(
+        &self,
+        out: &mut W,
+        stats: &[R],
+        template: &Template,
+        unit_style: UnitStyle,
+    ) -> io::Result<()> {
+        for ts in self.select(stats) {
+            for part in template.parts() {
+                match part {
+                    Part::Literal(s) => write!(out, "{}", s)?,
+                    Part::Field(field, _) => {
+                        write!(out, "{}", self.render_field(field, ts, unit_style))?
+                    }
+                }
+            }
         }
-        table.print(out)?;
         Ok(())
     }
 
-    pub fn print_full<W: Write>(&self, out: &mut W, stats: &[TaskStats]) -> io::Result<()> {
-        for ts in stats {
-            writeln!(out, "=== {} ===", self.header_format.format(ts.tid))?;
-            let cpu = ts.cpu;
-            writeln!(out, "--- CPU ---")?;
-            writeln!(out, "User Time (us): {}", cpu.utime_total.as_micros())?;
-            writeln!(out, "System Time (us): {}", cpu.stime_total.as_micros())?;
-            writeln!(out, "Real Time (us): {}", cpu.real_time_total.as_micros())?;
-            writeln!(
-                out,
-                "Virtual Time (us): {}",
-                cpu.virtual_time_total.as_micros()
-            )?;
-            let mem = ts.memory;
-            writeln!(out, "--- Memory ---")?;
-            writeln!(out, "RSS (MB-usec): {}", mem.rss_total)?;
-            writeln!(out, "Virtual (MB-usec): {}", mem.virt_total)?;
-            writeln!(
-                out,
-                "Page Faults (minor:major): {}:{}",
-                mem.minor_faults, mem.major_faults
-            )?;
-            let io = ts.io;
-            writeln!(out, "--- IO ---")?;
-            writeln!(out, "Read (bytes): {}", io.read_bytes)?;
-            writeln!(out, "Write (bytes): {}", io.write_bytes)?;
-            writeln!(
-                out,
-                "Syscalls (read:write): {}:{}",
-                io.read_syscalls, io.write_syscalls
-            )?;
-            let bio = ts.blkio;
-            writeln!(out, "--- Block Device IO ---")?;
-            writeln!(out, "Read (bytes): {}", bio.read_bytes)?;
-            writeln!(out, "Write (bytes): {}", bio.write_bytes)?;
-            writeln!(
-                out,
-                "Write Cancelled (bytes): {}",
-                bio.cancelled_write_bytes
-            )?;
-            let cswt = ts.ctx_switches;
-            writeln!(out, "--- Context Switches ---")?;
-            writeln!(
-                out,
-                "Voluntary:Non-voluntary: {}:{}",
-                cswt.voluntary, cswt.non_voluntary
-            )?;
-            let delays = ts.delays;
-            writeln!(out, "--- Delays ---")?;
-            writeln!(
-                out,
-                "CPU Total(nsec)/Count: {}/{}",
-                delays.cpu.delay_total.as_nanos(),
-                delays.cpu.count
-            )?;
-            writeln!(
-                out,
-                "BlkIO Total(nsec)/Count: {}/{}",
-                delays.blkio.delay_total.as_nanos(),
-                delays.blkio.count
-            )?;
-            writeln!(
-                out,
-                "SwapIn Total(nsec)/Count: {}/{}",
-                delays.swapin.delay_total.as_nanos(),
-                delays.swapin.count
-            )?;
+    fn write_delimited<W: Write, R: Record>(
+        &self,
+        out: &mut W,
+        stats: &[R],
+        columns: &[&Column],
+        delimiter: char,
+    ) -> io::Result<()> {
+        let names: Vec<_> = columns.iter().map(|c| c.name.to_string()).collect();
+        writeln!(out, "{}", names.join(&delimiter.to_string()))?;
+        for ts in self.select(stats) {
+            let fields: Vec<_> = columns
+                .iter()
+                .map(|c| {
+                    let value = unit::render(c, ts.stats(), self.unit_style, self.precision);
+                    escape_field(&value, delimiter)
+                })
+                .collect();
+            writeln!(out, "{}", fields.join(&delimiter.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Print stats as CSV with a header row of column names, a row per task.
+    pub fn print_csv<W: Write, R: Record>(
+        &self,
+        out: &mut W,
+        stats: &[R],
+        columns: &[&Column],
+    ) -> io::Result<()> {
+        self.write_delimited(out, stats, columns, ',')
+    }
+
+    /// Print stats as TSV with a header row of column names, a row per task.
+    pub fn print_tsv<W: Write, R: Record>(
+        &self,
+        out: &mut W,
+        stats: &[R],
+        columns: &[&Column],
+    ) -> io::Result<()> {
+        self.write_delimited(out, stats, columns, '\t')
+    }
+
+    pub fn print_full<W: Write, R: Record>(&self, out: &mut W, stats: &[R]) -> io::Result<()> {
+        self.write_template_text(out, stats, &Template::full(), UnitStyle::Raw)
+    }
+
+    /// Print stats as a JSON array of objects.
+    ///
+    /// Each object has every field of `TaskStats` as a flat key, suffixed by
+    /// its unit when it has one (e.g. `utime_us`, `cpu_delay_ns`).
+    /// Records enriched with their containers also have `cgroup`,
+    /// `container_runtime`, `container_id`, `pod_uid`, `pid_ns` and `ns_pid`,
+    /// which are null if unresolved.
+    pub fn print_json<W: Write, R: Record>(&self, out: &mut W, stats: &[R]) -> io::Result<()> {
+        writeln!(out, "[")?;
+        let selected = self.select(stats);
+        for (i, record) in selected.iter().enumerate() {
+            write!(out, "  ")?;
+            write_json_object(out, *record)?;
+            if i + 1 < selected.len() {
+                write!(out, ",")?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out, "]")?;
+        Ok(())
+    }
+
+    /// Print stats as newline delimited JSON, an object per line.
+    ///
+    /// Objects are the same as the ones `print_json` outputs.
+    pub fn print_ndjson<W: Write, R: Record>(&self, out: &mut W, stats: &[R]) -> io::Result<()> {
+        for ts in self.select(stats) {
+            write_json_object(out, ts)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// Print stats in the InfluxDB line protocol, a line of the measurement
+    /// `taskstats` per task.
+    ///
+    /// Lines are tagged by `tid`, `tgid`, `comm` and `uid`, where `tgid` is
+    /// `TaskStats::tgid`, or read from /proc (see `with_procfs`) on kernels not
+    /// reporting it and left out once the task has gone. Records enriched
+    /// with their containers are also tagged by `container_id`, `pod_uid`,
+    /// `pid_ns` and `ns_pid` if resolved. Fields are all counters with the same
+    /// keys as `print_json`, written as signed integers clamped at `i64::MAX`
+    /// which InfluxDB accepts without unsigned support, and lines are stamped
+    /// by `timestamp` in nanoseconds.
+    pub fn print_influx<W: Write, R: Record>(
+        &self,
+        out: &mut W,
+        stats: &[R],
+        timestamp: SystemTime,
+    ) -> io::Result<()> {
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        for record in self.select(stats) {
+            let ts = record.stats();
+            let container = record.container().cloned().unwrap_or_default();
+            let tags = [
+                ("tid", Some(ts.tid.to_string())),
+                (
+                    "tgid",
+                    ts.tgid()
+                        .or_else(|| self.procfs.tgid(ts.tid))
+                        .map(|t| t.to_string()),
+                ),
+                ("comm", Some(ts.comm())),
+                ("uid", Some(ts.inner().ac_uid.to_string())),
+                ("container_id", container.container_id),
+                ("pod_uid", container.pod_uid),
+                ("pid_ns", container.pid_ns.map(|ns| ns.to_string())),
+                ("ns_pid", container.ns_pid.map(|pid| pid.to_string())),
+            ];
+            write!(out, "taskstats")?;
+            for (key, value) in tags {
+                match value {
+                    Some(value) if !value.is_empty() => {
+                        write!(out, ",{}={}", key, escape_influx_tag(&value))?
+                    }
+                    _ => {}
+                }
+            }
+            let fields: Vec<_> = JSON_FIELDS
+                .iter()
+                .filter(|(key, _)| *key != "tid")
+                .map(|(key, value)| format!("{}={}i", key, value(ts).min(i64::MAX as u128)))
+                .collect();
+            writeln!(out, " {} {}", fields.join(","), timestamp)?;
+        }
+        Ok(())
+    }
+
+    /// Print a histogram of task lifetimes made by `report::lifetime_histogram`,
+    /// a line per bucket with a bar scaled to the largest bucket.
+    ///
+    /// Bounds of buckets are in microseconds with `UnitStyle::Raw`, or
+    /// rendered like `1.0ms` otherwise.
+    pub fn print_histogram<W: Write>(&self, out: &mut W, buckets: &[Bucket]) -> io::Result<()> {
+        const BAR_WIDTH: usize = 40;
+        let render = |d: Duration| match self.unit_style {
+            UnitStyle::Raw => d.as_micros().to_string(),
+            _ => unit::format_nanos(d.as_nanos(), self.precision),
+        };
+        let ranges: Vec<_> = buckets
+            .iter()
+            .map(|b| format!("[{}, {})", render(b.min), render(b.max)))
+            .collect();
+        let title = match self.unit_style {
+            UnitStyle::Raw => "lifetime (us)",
+            _ => "lifetime",
+        };
+        let width = ranges
+            .iter()
+            .map(|r| r.len())
+            .chain([title.len()])
+            .max()
+            .unwrap();
+        let max = buckets.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+        writeln!(out, "{:<width$} {:>8}", title, "count", width = width)?;
+        for (range, bucket) in ranges.iter().zip(buckets) {
+            let bar = "@".repeat(bucket.count * BAR_WIDTH / max);
             writeln!(
                 out,
-                "Mem Reclaim Total(nsec)/Count: {}/{}",
-                delays.freepages.delay_total.as_nanos(),
-                delays.freepages.count
+                "{:<width$} {:>8} |{:<bar_width$}|",
+                range,
+                bucket.count,
+                bar,
+                width = width,
+                bar_width = BAR_WIDTH
             )?;
         }
         Ok(())
     }
 }
 
+/// Escape a tag value of the InfluxDB line protocol. Line breaks, which the
+/// protocol can't carry in tags, are written as `\n` and `\r`.
+fn escape_influx_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            ',' | '=' | ' ' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::container::{ContainerInfo, Enriched, Runtime};
     use crate::model::*;
     use std::time::Duration;
 
-    const TS: TaskStats = TaskStats {
-        tid: 1234,
-        cpu: Cpu {
-            utime_total: Duration::from_micros(12),
-            stime_total: Duration::from_micros(34),
-            real_time_total: Duration::from_micros(56),
-            virtual_time_total: Duration::from_micros(78),
-        },
-        memory: Memory {
-            rss_total: 12,
-            virt_total: 34,
-            minor_faults: 56,
-            major_faults: 78,
-        },
-        io: Io {
-            read_bytes: 12,
-            write_bytes: 34,
-            read_syscalls: 56,
-            write_syscalls: 78,
-        },
-        blkio: BlkIo {
-            read_bytes: 12,
-            write_bytes: 34,
-            cancelled_write_bytes: 56,
-        },
-        ctx_switches: ContextSwitches {
-            voluntary: 12,
-            non_voluntary: 34,
-        },
-        delays: Delays {
-            cpu: DelayStat {
-                count: 12,
-                delay_total: Duration::from_nanos(34),
-            },
-            blkio: DelayStat {
-                count: 56,
-                delay_total: Duration::from_nanos(78),
-            },
-            swapin: DelayStat {
-                count: 123,
-                delay_total: Duration::from_nanos(456),
-            },
-            freepages: DelayStat {
-                count: 789,
-                delay_total: Duration::from_nanos(1234),
-            },
-        },
-        inner_buf: [0u8; TASKSTATS_SIZE],
-    };
+    fn ts() -> TaskStats {
+        TaskStats::builder()
+            .with_tid(1234)
+            .with_cpu(Cpu {
+                utime_total: Duration::from_micros(12),
+                stime_total: Duration::from_micros(34),
+                real_time_total: Duration::from_micros(56),
+                virtual_time_total: Duration::from_micros(78),
+            })
+            .with_memory(Memory {
+                rss_total: 12,
+                virt_total: 34,
+                minor_faults: 56,
+                major_faults: 78,
+            })
+            .with_io(Io {
+                read_bytes: 12,
+                write_bytes: 34,
+                read_syscalls: 56,
+                write_syscalls: 78,
+            })
+            .with_blkio(BlkIo {
+                read_bytes: 12,
+                write_bytes: 34,
+                cancelled_write_bytes: 56,
+            })
+            .with_ctx_switches(ContextSwitches {
+                voluntary: 12,
+                non_voluntary: 34,
+            })
+            .with_delays(Delays {
+                cpu: DelayStat {
+                    count: 12,
+                    delay_total: Duration::from_nanos(34),
+                },
+                blkio: DelayStat {
+                    count: 56,
+                    delay_total: Duration::from_nanos(78),
+                },
+                swapin: DelayStat {
+                    count: 123,
+                    delay_total: Duration::from_nanos(456),
+                },
+                freepages: DelayStat {
+                    count: 789,
+                    delay_total: Duration::from_nanos(1234),
+                },
+            })
+            .build()
+    }
+
+    #[test]
+    fn test_print_influx() {
+        let printer = Printer::new(DefaultHeaderFormat::new());
+        let mut out = Vec::new();
+        let timestamp = UNIX_EPOCH + Duration::from_nanos(1_680_000_000_123_456_789);
+        printer.print_influx(&mut out, &[ts()], timestamp).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(1, out.lines().count());
+        assert!(out.starts_with("taskstats,tid=1234,"), "{}", out);
+        assert!(out.contains(",uid=0 utime_us=12i,stime_us=34i,"), "{}", out);
+        assert!(
+            out.ends_with(",freepages_delay_ns=1234i 1680000000123456789\n"),
+            "{}",
+            out
+        );
+
+        // The fixture tells 1235 is a thread of 1234 and doesn't know 4321
+        let printer = printer.with_procfs(Arc::new(procfs::tests::fixture()));
+        let exited = TaskStats::builder()
+            .with_tid(4321)
+            .with_tgid(4320)
+            .with_io(Io {
+                read_bytes: u64::MAX,
+                ..Default::default()
+            })
+            .build();
+        let thread = TaskStats::builder().with_tid(1235).build();
+        let gone = TaskStats::builder().with_tid(4321).build();
+        let mut out = Vec::new();
+        printer
+            .print_influx(&mut out, &[exited, thread, gone], timestamp)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert!(
+            lines[0].contains(",read_bytes=9223372036854775807i,"),
+            "{}",
+            out
+        );
+        if exited.tgid().is_some() {
+            assert!(
+                lines[0].starts_with("taskstats,tid=4321,tgid=4320,"),
+                "{}",
+                out
+            );
+        } else {
+            // Built against headers without ac_tgid, the gone task has no tgid
+            assert!(lines[0].starts_with("taskstats,tid=4321,uid=0 "), "{}", out);
+        }
+        assert!(
+            lines[1].starts_with("taskstats,tid=1235,tgid=1234,"),
+            "{}",
+            out
+        );
+        assert!(lines[2].starts_with("taskstats,tid=4321,uid=0 "), "{}", out);
+    }
+
+    #[test]
+    fn test_escape_influx_tag() {
+        assert_eq!("a\\ b\\,c\\=d", escape_influx_tag("a b,c=d"));
+        assert_eq!("a\\nb\\r", escape_influx_tag("a\nb\r"));
+    }
 
     #[test]
     fn test_print_summary_lines() {
@@ -247,20 +697,22 @@ mod tests {
 
         let printer = Printer::new(DefaultHeaderFormat::new());
         let mut out = Vec::new();
-        printer.print_summary_lines(&mut out, &[TS, TS]).unwrap();
+        printer
+            .print_summary_lines(&mut out, &[ts(), ts()])
+            .unwrap();
         assert_eq!(expect, String::from_utf8(out).unwrap());
     }
 
     #[test]
     fn test_print_delay_lines() {
-        let expect = "   Task    | cpu avg | blkio avg | swapin avg | reclaim avg | cpu total | blkio total | swapin total | reclaim total 
- TID: 1234 |       2 |         1 |          3 |           1 |        34 |          78 |          456 |          1234 
- TID: 1234 |       2 |         1 |          3 |           1 |        34 |          78 |          456 |          1234 
+        let expect = "   Task    | cpu avg | blkio avg | swapin avg | reclaim avg | cpu total | blkio total | swapin total | reclaim total | cpu % | blkio % | swapin % | reclaim % 
+ TID: 1234 |       2 |         1 |          3 |           1 |        34 |          78 |          456 |          1234 |   0.0 |     0.0 |      0.0 |       0.0 
+ TID: 1234 |       2 |         1 |          3 |           1 |        34 |          78 |          456 |          1234 |   0.0 |     0.0 |      0.0 |       0.0 
 ";
 
         let printer = Printer::new(DefaultHeaderFormat::new());
         let mut out = Vec::new();
-        printer.print_delay_lines(&mut out, &[TS, TS]).unwrap();
+        printer.print_delay_lines(&mut out, &[ts(), ts()]).unwrap();
         assert_eq!(expect, String::from_utf8(out).unwrap());
     }
 
@@ -295,7 +747,278 @@ Mem Reclaim Total(nsec)/Count: 1234/789
 
         let printer = Printer::new(DefaultHeaderFormat::new());
         let mut out = Vec::new();
-        printer.print_full(&mut out, &[TS]).unwrap();
+        printer.print_full(&mut out, &[ts()]).unwrap();
+        assert_eq!(expect, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_print_json() {
+        let object = r#"{"tid":1234,"utime_us":12,"stime_us":34,"real_time_ns":56000,"virtual_time_ns":78000,"rss_total_mb_us":12,"virt_total_mb_us":34,"minor_faults":56,"major_faults":78,"read_bytes":12,"write_bytes":34,"read_syscalls":56,"write_syscalls":78,"blkio_read_bytes":12,"blkio_write_bytes":34,"blkio_cancelled_write_bytes":56,"voluntary_ctx_switches":12,"non_voluntary_ctx_switches":34,"cpu_delay_count":12,"cpu_delay_ns":34,"blkio_delay_count":56,"blkio_delay_ns":78,"swapin_delay_count":123,"swapin_delay_ns":456,"freepages_delay_count":789,"freepages_delay_ns":1234}"#;
+        let expect = format!("[\n  {},\n  {}\n]\n", object, object);
+
+        let printer = Printer::new(DefaultHeaderFormat::new());
+        let mut out = Vec::new();
+        printer.print_json(&mut out, &[ts(), ts()]).unwrap();
         assert_eq!(expect, String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        printer.print_json::<_, TaskStats>(&mut out, &[]).unwrap();
+        assert_eq!("[\n]\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_print_ndjson() {
+        let printer = Printer::new(DefaultHeaderFormat::new());
+        let mut out = Vec::new();
+        printer.print_ndjson(&mut out, &[ts(), ts()]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].starts_with(r#"{"tid":1234,"utime_us":12,"#));
+        assert!(lines[1].ends_with(r#""freepages_delay_ns":1234}"#));
+    }
+
+    #[test]
+    fn test_print_enriched() {
+        let enriched = Enriched {
+            stats: ts(),
+            container: Some(ContainerInfo {
+                cgroup: Some("/a \"b\"".to_string()),
+                runtime: Some(Runtime::Podman),
+                container_id: Some("0123456789ab".repeat(5) + "cdef"),
+                pid_ns: Some(4026532448),
+                ns_pid: Some(1),
+                ..Default::default()
+            }),
+        };
+        let printer = Printer::new(ContainerHeaderFormat::new());
+
+        let mut out = Vec::new();
+        printer
+            .print_ndjson(&mut out, std::slice::from_ref(&enriched))
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with(
+            r#""freepages_delay_ns":1234,"cgroup":"/a \"b\"","container_runtime":"podman","container_id":"0123456789ab0123456789ab0123456789ab0123456789ab0123456789abcdef","pod_uid":null,"pid_ns":4026532448,"ns_pid":1}
+"#
+        ));
+
+        let mut out = Vec::new();
+        let template = Template::parse("%task\n").unwrap();
+        printer
+            .print_template_text(&mut out, std::slice::from_ref(&enriched), &template)
+            .unwrap();
+        assert_eq!("0123456789ab (1234)\n", String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        printer
+            .print_influx(&mut out, &[enriched], UNIX_EPOCH)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(",container_id=0123456789ab"));
+        assert!(out.contains(",pid_ns=4026532448,ns_pid=1 "));
+    }
+
+    #[test]
+    fn test_print_table() {
+        let expect = "   Task    | tid  | utime | d:cpu 
+ TID: 1234 | 1234 |    12 |    34 
+";
+
+        let printer = Printer::new(DefaultHeaderFormat::new());
+        let columns: Vec<_> = ["tid", "utime", "cpu_delay"]
+            .iter()
+            .map(|name| Column::by_name(name).unwrap())
+            .collect();
+        let mut out = Vec::new();
+        printer.print_table(&mut out, &[ts()], &columns).unwrap();
+        assert_eq!(expect, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_print_csv() {
+        let expect = "tid,comm,utime,cpu_delay,blkio_delay_avg
+1234,,12,34,1
+1234,,12,34,1
+";
+
+        let printer = Printer::new(DefaultHeaderFormat::new());
+        let columns: Vec<_> = ["tid", "comm", "utime", "cpu_delay", "blkio_delay_avg"]
+            .iter()
+            .map(|name| Column::by_name(name).unwrap())
+            .collect();
+        let mut out = Vec::new();
+        printer
+            .print_csv(&mut out, &[ts(), ts()], &columns)
+            .unwrap();
+        assert_eq!(expect, String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        printer.print_tsv(&mut out, &[ts()], &columns).unwrap();
+        assert_eq!(
+            "tid\tcomm\tutime\tcpu_delay\tblkio_delay_avg\n1234\t\t12\t34\t1\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_escape_field() {
+        assert_eq!("a b", escape_field("a b", ','));
+        assert_eq!("\"a,b\"", escape_field("a,b", ','));
+        assert_eq!("\"a\"\"b\"", escape_field("a\"b", ','));
+        assert_eq!("a b", escape_field("a\tb", '\t'));
+    }
+
+    #[test]
+    fn test_print_template() {
+        let printer = Printer::new(DefaultHeaderFormat::new());
+        let template = Template::parse("%task %comm: cpu=%cpu_delay_avg\n").unwrap();
+        let mut out = Vec::new();
+        printer
+            .print_template_text(&mut out, &[ts(), ts()], &template)
+            .unwrap();
+        assert_eq!(
+            "TID: 1234 : cpu=2\nTID: 1234 : cpu=2\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let expect = " TID  | blkio 
+ 1234 |    78 
+";
+        let template = Template::from_fields("tid=TID,blkio_delay=blkio").unwrap();
+        let mut out = Vec::new();
+        printer
+            .print_template_table(&mut out, &[ts()], &template)
+            .unwrap();
+        assert_eq!(expect, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_print_human_units() {
+        let ts = TaskStats::builder()
+            .with_tid(1234)
+            .with_cpu(Cpu {
+                utime_total: Duration::from_millis(1500),
+                stime_total: Duration::from_millis(500),
+                ..Default::default()
+            })
+            .with_memory(Memory {
+                rss_total: 2_000_000 * 4,
+                ..Default::default()
+            })
+            .with_io(Io {
+                read_bytes: 3 << 20,
+                ..Default::default()
+            })
+            .with_delays(Delays {
+                cpu: DelayStat {
+                    count: 1,
+                    delay_total: Duration::from_micros(12_345),
+                },
+                ..Default::default()
+            })
+            .build();
+
+        let printer = Printer::new(DefaultHeaderFormat::new())
+            .with_unit_style(UnitStyle::Human)
+            .with_precision(2);
+        let columns: Vec<_> = ["tid", "utime", "rss", "read", "cpu_delay"]
+            .iter()
+            .map(|name| Column::by_name(name).unwrap())
+            .collect();
+        let mut out = Vec::new();
+        printer.print_csv(&mut out, &[ts], &columns).unwrap();
+        assert_eq!(
+            "tid,utime,rss,read,cpu_delay\n1234,1.50s,4.00MiB,3.00MiB,12.35ms\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_print_histogram() {
+        let bucket = |min, max, count| Bucket {
+            min: Duration::from_micros(min),
+            max: Duration::from_micros(max),
+            count,
+        };
+        let buckets = [bucket(512, 1024, 4), bucket(1024, 2048, 1)];
+        let mut out = Vec::new();
+        Printer::new(DefaultHeaderFormat::new())
+            .print_histogram(&mut out, &buckets)
+            .unwrap();
+        assert_eq!(
+            "lifetime (us)    count\n\
+             [512, 1024)          4 |@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@|\n\
+             [1024, 2048)         1 |@@@@@@@@@@                              |\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        Printer::new(DefaultHeaderFormat::new())
+            .with_unit_style(UnitStyle::Human)
+            .print_histogram(&mut out, &buckets)
+            .unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("[512.0us, 1.0ms)"));
+    }
+
+    #[test]
+    fn test_print_selection() {
+        let mut stats = Vec::new();
+        for (tid, delay) in [(1, 30), (2, 10), (3, 50), (4, 20)].iter() {
+            let delays = Delays {
+                cpu: DelayStat {
+                    count: 1,
+                    delay_total: Duration::from_millis(*delay),
+                },
+                ..Default::default()
+            };
+            stats.push(
+                TaskStats::builder()
+                    .with_tid(*tid)
+                    .with_delays(delays)
+                    .build(),
+            );
+        }
+        let cpu_delay = Column::by_name("cpu_delay").unwrap();
+        let columns = [Column::by_name("tid").unwrap()];
+        let print = |printer: Printer<DefaultHeaderFormat>| {
+            let mut out = Vec::new();
+            printer.print_csv(&mut out, &stats, &columns).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let printer =
+            Printer::new(DefaultHeaderFormat::new()).with_sort(cpu_delay, Order::Descending);
+        assert_eq!("tid\n3\n1\n4\n2\n", print(printer));
+
+        let printer = Printer::new(DefaultHeaderFormat::new())
+            .with_sort(cpu_delay, Order::Ascending)
+            .with_top(2);
+        assert_eq!("tid\n2\n4\n", print(printer));
+
+        let min = cpu_delay.parse_value("20ms").unwrap();
+        let printer = Printer::new(DefaultHeaderFormat::new()).with_min(cpu_delay, min.clone());
+        assert_eq!("tid\n1\n3\n4\n", print(printer));
+
+        let printer = Printer::new(DefaultHeaderFormat::new())
+            .with_min(cpu_delay, min)
+            .with_sort(cpu_delay, Order::Descending)
+            .with_top(1);
+        let mut out = Vec::new();
+        printer.print_ndjson(&mut out, &stats).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with(r#"{"tid":3,"#));
+
+        let cpu_delay_pct = Column::by_name("cpu_delay_pct").unwrap();
+        let min_pct = cpu_delay_pct.parse_value("0.5%").unwrap();
+        let by_pct = Printer::new(DefaultHeaderFormat::new()).with_min(cpu_delay_pct, min_pct);
+        assert_eq!("tid\n", print(by_pct));
+
+        let selected: Vec<_> = printer
+            .select(&stats)
+            .into_iter()
+            .map(|ts| ts.tid)
+            .collect();
+        assert_eq!(vec![3], selected);
+        assert_eq!("tid\n1\n2\n3\n4\n", print(printer.without_selection()));
     }
 }
@@ -0,0 +1,441 @@
+use crate::TaskStats;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Unit of values of a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// Counts, IDs or texts which have no unit
+    None,
+    /// Duration in microseconds
+    Micros,
+    /// Duration in nanoseconds
+    Nanos,
+    /// Size in bytes
+    Bytes,
+    /// Memory usage integrated over CPU time, in MB-usecs
+    MbUsecs,
+    /// Percentage, as a float
+    Percent,
+}
+
+/// A value taken out of `TaskStats` for a column.
+///
+/// Values are totally ordered so that any column can be sorted by, with
+/// floats compared by `f64::total_cmp`.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(u128),
+    Float(f64),
+    Text(String),
+}
+
+impl Value {
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Int(_) => 0,
+            Value::Float(_) => 1,
+            Value::Text(_) => 2,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(v) => v.fmt(f),
+            Value::Float(v) => v.fmt(f),
+            Value::Text(v) => v.fmt(f),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+/// A named field of `TaskStats` which can be printed as a column.
+///
+/// All available columns are listed by `Column::all()` and looked up by
+/// `Column::by_name()`, which are shared by table and CSV outputs.
+pub struct Column {
+    /// Name to select the column by
+    pub name: &'static str,
+    /// Title printed in the header of a table
+    pub title: &'static str,
+    /// Short description including the unit of the value
+    pub description: &'static str,
+    /// Unit of the value
+    pub unit: Unit,
+    extract: fn(&TaskStats) -> Value,
+}
+
+impl Column {
+    /// Return all available columns.
+    pub fn all() -> &'static [Column] {
+        COLUMNS
+    }
+
+    /// Find a column by its name.
+    pub fn by_name(name: &str) -> Option<&'static Column> {
+        COLUMNS.iter().find(|c| c.name == name)
+    }
+
+    /// Take the value of this column out of the given stats.
+    pub fn value(&self, ts: &TaskStats) -> Value {
+        (self.extract)(ts)
+    }
+
+    /// Parse a value in the unit of this column, e.g. `10ms` for a column in
+    /// nanoseconds or `0.5%` for a percentage, into the raw value as returned
+    /// by `value`.
+    pub fn parse_value(&self, s: &str) -> Option<Value> {
+        super::unit::parse_value(self.unit, s)
+    }
+}
+
+impl fmt::Debug for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Column").field("name", &self.name).finish()
+    }
+}
+
+macro_rules! int_column {
+    ($name:expr, $title:expr, $description:expr, $unit:ident, $($field:tt)+) => {
+        Column {
+            name: $name,
+            title: $title,
+            description: $description,
+            unit: Unit::$unit,
+            extract: |ts| Value::Int(ts.$($field)+ as u128),
+        }
+    };
+}
+
+macro_rules! delay_avg_column {
+    ($name:expr, $title:expr, $description:expr, $delay:ident) => {
+        Column {
+            name: $name,
+            title: $title,
+            description: $description,
+            unit: Unit::Nanos,
+            extract: |ts| Value::Int(ts.delays.$delay.average().as_nanos()),
+        }
+    };
+}
+
+macro_rules! delay_pct_column {
+    ($name:expr, $title:expr, $description:expr, $delay:ident) => {
+        Column {
+            name: $name,
+            title: $title,
+            description: $description,
+            unit: Unit::Percent,
+            extract: |ts| Value::Float(ts.delays.$delay.percent_of(ts.elapsed())),
+        }
+    };
+}
+
+/// Running times in microseconds, printed by `Printer::print_full` as they
+/// were before columns existed, while the registered ones are in nanoseconds
+pub(super) static REAL_TIME_US: Column = int_column!(
+    "real_time",
+    "real",
+    "wall-clock running time (us)",
+    Micros,
+    cpu.real_time_total.as_micros()
+);
+pub(super) static VIRTUAL_TIME_US: Column = int_column!(
+    "virtual_time",
+    "virtual",
+    "virtual running time (us)",
+    Micros,
+    cpu.virtual_time_total.as_micros()
+);
+
+const COLUMNS: &[Column] = &[
+    int_column!("tid", "tid", "task ID", None, tid),
+    Column {
+        name: "comm",
+        title: "comm",
+        description: "command name",
+        unit: Unit::None,
+        extract: |ts| Value::Text(ts.comm()),
+    },
+    int_column!(
+        "utime",
+        "utime",
+        "user CPU time (us)",
+        Micros,
+        cpu.utime_total.as_micros()
+    ),
+    int_column!(
+        "stime",
+        "stime",
+        "system CPU time (us)",
+        Micros,
+        cpu.stime_total.as_micros()
+    ),
+    int_column!(
+        "real_time",
+        "real",
+        "wall-clock running time (ns)",
+        Nanos,
+        cpu.real_time_total.as_nanos()
+    ),
+    int_column!(
+        "virtual_time",
+        "virtual",
+        "virtual running time (ns)",
+        Nanos,
+        cpu.virtual_time_total.as_nanos()
+    ),
+    int_column!(
+        "elapsed",
+        "elapsed",
+        "elapsed time since the task started (us)",
+        Micros,
+        inner().ac_etime
+    ),
+    int_column!(
+        "rss",
+        "rss",
+        "accumulated RSS usage (MB-usec)",
+        MbUsecs,
+        memory.rss_total
+    ),
+    int_column!(
+        "vmem",
+        "vmem",
+        "accumulated virtual memory usage (MB-usec)",
+        MbUsecs,
+        memory.virt_total
+    ),
+    int_column!(
+        "minflt",
+        "minflt",
+        "minor page faults",
+        None,
+        memory.minor_faults
+    ),
+    int_column!(
+        "majflt",
+        "majflt",
+        "major page faults",
+        None,
+        memory.major_faults
+    ),
+    int_column!(
+        "read",
+        "read",
+        "bytes read by syscalls",
+        Bytes,
+        io.read_bytes
+    ),
+    int_column!(
+        "write",
+        "write",
+        "bytes written by syscalls",
+        Bytes,
+        io.write_bytes
+    ),
+    int_column!(
+        "read_syscalls",
+        "read sc",
+        "read syscalls",
+        None,
+        io.read_syscalls
+    ),
+    int_column!(
+        "write_syscalls",
+        "write sc",
+        "write syscalls",
+        None,
+        io.write_syscalls
+    ),
+    int_column!(
+        "blkio_read",
+        "bio read",
+        "bytes read from block devices",
+        Bytes,
+        blkio.read_bytes
+    ),
+    int_column!(
+        "blkio_write",
+        "bio write",
+        "bytes written to block devices",
+        Bytes,
+        blkio.write_bytes
+    ),
+    int_column!(
+        "blkio_cancelled_write",
+        "bio cancelled",
+        "bytes of cancelled writes",
+        Bytes,
+        blkio.cancelled_write_bytes
+    ),
+    int_column!(
+        "nvcsw",
+        "nvcsw",
+        "voluntary context switches",
+        None,
+        ctx_switches.voluntary
+    ),
+    int_column!(
+        "nivcsw",
+        "nivcsw",
+        "non-voluntary context switches",
+        None,
+        ctx_switches.non_voluntary
+    ),
+    int_column!(
+        "cpu_count",
+        "cpu count",
+        "count of CPU delays",
+        None,
+        delays.cpu.count
+    ),
+    int_column!(
+        "cpu_delay",
+        "d:cpu",
+        "total CPU delay (ns)",
+        Nanos,
+        delays.cpu.delay_total.as_nanos()
+    ),
+    delay_avg_column!("cpu_delay_avg", "cpu avg", "average CPU delay (ns)", cpu),
+    delay_pct_column!(
+        "cpu_delay_pct",
+        "cpu %",
+        "CPU delay in % of elapsed time",
+        cpu
+    ),
+    int_column!(
+        "blkio_count",
+        "blkio count",
+        "count of block I/O delays",
+        None,
+        delays.blkio.count
+    ),
+    int_column!(
+        "blkio_delay",
+        "d:bio",
+        "total block I/O delay (ns)",
+        Nanos,
+        delays.blkio.delay_total.as_nanos()
+    ),
+    delay_avg_column!(
+        "blkio_delay_avg",
+        "blkio avg",
+        "average block I/O delay (ns)",
+        blkio
+    ),
+    delay_pct_column!(
+        "blkio_delay_pct",
+        "blkio %",
+        "block I/O delay in % of elapsed time",
+        blkio
+    ),
+    int_column!(
+        "swapin_count",
+        "swapin count",
+        "count of swap in delays",
+        None,
+        delays.swapin.count
+    ),
+    int_column!(
+        "swapin_delay",
+        "d:swap",
+        "total swap in delay (ns)",
+        Nanos,
+        delays.swapin.delay_total.as_nanos()
+    ),
+    delay_avg_column!(
+        "swapin_delay_avg",
+        "swapin avg",
+        "average swap in delay (ns)",
+        swapin
+    ),
+    delay_pct_column!(
+        "swapin_delay_pct",
+        "swapin %",
+        "swap in delay in % of elapsed time",
+        swapin
+    ),
+    int_column!(
+        "freepages_count",
+        "reclaim count",
+        "count of memory reclaim delays",
+        None,
+        delays.freepages.count
+    ),
+    int_column!(
+        "freepages_delay",
+        "d:reclaim",
+        "total memory reclaim delay (ns)",
+        Nanos,
+        delays.freepages.delay_total.as_nanos()
+    ),
+    delay_avg_column!(
+        "freepages_delay_avg",
+        "reclaim avg",
+        "average memory reclaim delay (ns)",
+        freepages
+    ),
+    delay_pct_column!(
+        "freepages_delay_pct",
+        "reclaim %",
+        "memory reclaim delay in % of elapsed time",
+        freepages
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{taskstats, AsBuf};
+    use std::mem;
+
+    #[test]
+    fn test_column_value() {
+        let mut raw: taskstats = unsafe { mem::zeroed() };
+        raw.ac_pid = 1234;
+        raw.ac_comm[..3].copy_from_slice(&[b'f' as _, b'o' as _, b'o' as _]);
+        raw.cpu_count = 2;
+        raw.cpu_delay_total = 10;
+        raw.ac_etime = 1;
+        raw.cpu_run_real_total = 1500;
+        let ts = TaskStats::from(raw.as_buf());
+
+        let value = |name| Column::by_name(name).unwrap().value(&ts);
+        assert_eq!(Value::Int(1234), value("tid"));
+        assert_eq!(Value::Text("foo".to_string()), value("comm"));
+        assert_eq!(Value::Int(5), value("cpu_delay_avg"));
+        assert_eq!(Value::Float(1.0), value("cpu_delay_pct"));
+        assert_eq!(Value::Int(1), value("elapsed"));
+        assert_eq!(Value::Int(1500), value("real_time"));
+        assert_eq!(Value::Int(1), REAL_TIME_US.value(&ts));
+        assert!(Value::Float(1.5) < Value::Float(2.0));
+        assert!(Column::by_name("nonexistent").is_none());
+    }
+}
@@ -0,0 +1,296 @@
+use super::HeaderFormat;
+use crate::container::{ContainerInfo, Record};
+use crate::procfs::{self, ProcFs};
+use crate::TaskStats;
+use std::ffi::CStr;
+use std::mem;
+use std::ptr;
+use std::sync::Arc;
+
+/// Labels a task by its command name, e.g. `nginx (1234)`.
+#[derive(Default)]
+pub struct CommHeaderFormat {}
+
+impl CommHeaderFormat {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl HeaderFormat for CommHeaderFormat {
+    fn format(&self, ts: &TaskStats) -> String {
+        format!("{} ({})", ts.comm(), ts.tid)
+    }
+}
+
+/// Labels a task by its command line read from /proc, truncated to `max_len` characters.
+///
+/// Falls back to the command name in brackets like `ps` does when the command
+/// line isn't available, e.g. for kernel threads or exited tasks.
+pub struct CmdlineHeaderFormat {
+    max_len: usize,
+    procfs: Arc<dyn ProcFs>,
+}
+
+impl CmdlineHeaderFormat {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            procfs: procfs::system(),
+        }
+    }
+
+    /// Read command lines through `procfs` instead of /proc.
+    pub fn with_procfs(mut self, procfs: Arc<dyn ProcFs>) -> Self {
+        self.procfs = procfs;
+        self
+    }
+}
+
+impl HeaderFormat for CmdlineHeaderFormat {
+    fn format(&self, ts: &TaskStats) -> String {
+        let cmdline = self
+            .procfs
+            .cmdline(ts.tid)
+            .unwrap_or_else(|| format!("[{}]", ts.comm()));
+        truncate(&cmdline, self.max_len)
+    }
+}
+
+/// Labels a task by the name of its user, e.g. `root (1234)`.
+///
+/// Falls back to the numeric user ID when it has no entry in the user database.
+pub struct UserHeaderFormat {
+    lookup: fn(u32) -> Option<String>,
+}
+
+impl Default for UserHeaderFormat {
+    fn default() -> Self {
+        Self { lookup: username }
+    }
+}
+
+impl UserHeaderFormat {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Look up names of users by `lookup` instead of the user database.
+    pub fn with_lookup(mut self, lookup: fn(u32) -> Option<String>) -> Self {
+        self.lookup = lookup;
+        self
+    }
+}
+
+impl HeaderFormat for UserHeaderFormat {
+    fn format(&self, ts: &TaskStats) -> String {
+        let uid = ts.inner().ac_uid;
+        let user = (self.lookup)(uid).unwrap_or_else(|| uid.to_string());
+        format!("{} ({})", user, ts.tid)
+    }
+}
+
+/// Labels a task by the path of its cgroup read from /proc, e.g. `/system.slice/nginx.service (1234)`.
+///
+/// The path in the cgroup v2 hierarchy is preferred, then the one of the first
+/// v1 hierarchy listed. The path resolved along with an enriched record is
+/// used instead if any.
+pub struct CgroupHeaderFormat {
+    procfs: Arc<dyn ProcFs>,
+}
+
+impl Default for CgroupHeaderFormat {
+    fn default() -> Self {
+        Self {
+            procfs: procfs::system(),
+        }
+    }
+}
+
+impl CgroupHeaderFormat {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Read cgroups of records not enriched through `procfs` instead of /proc.
+    pub fn with_procfs(mut self, procfs: Arc<dyn ProcFs>) -> Self {
+        self.procfs = procfs;
+        self
+    }
+}
+
+impl HeaderFormat for CgroupHeaderFormat {
+    fn format(&self, ts: &TaskStats) -> String {
+        self.format_record(ts)
+    }
+
+    fn format_record(&self, record: &dyn Record) -> String {
+        let cgroup = container_of(record, &*self.procfs)
+            .cgroup
+            .unwrap_or_else(|| "?".to_string());
+        format!("{} ({})", cgroup, record.stats().tid)
+    }
+}
+
+/// Labels a task by the ID of the container it runs in, shortened to 12
+/// characters as `docker ps` does, e.g. `4f2a3c9b1d0e (1234)`.
+///
+/// The ID is taken from the task's cgroup path, resolved along with the
+/// record if enriched. Tasks outside containers are labeled `-`.
+pub struct ContainerHeaderFormat {
+    procfs: Arc<dyn ProcFs>,
+}
+
+impl Default for ContainerHeaderFormat {
+    fn default() -> Self {
+        Self {
+            procfs: procfs::system(),
+        }
+    }
+}
+
+impl ContainerHeaderFormat {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Read cgroups of records not enriched through `procfs` instead of /proc.
+    pub fn with_procfs(mut self, procfs: Arc<dyn ProcFs>) -> Self {
+        self.procfs = procfs;
+        self
+    }
+}
+
+impl HeaderFormat for ContainerHeaderFormat {
+    fn format(&self, ts: &TaskStats) -> String {
+        self.format_record(ts)
+    }
+
+    fn format_record(&self, record: &dyn Record) -> String {
+        let container = container_of(record, &*self.procfs);
+        let id = container.short_id().unwrap_or("-");
+        format!("{} ({})", id, record.stats().tid)
+    }
+}
+
+fn truncate(s: &str, max_len: usize) -> String {
+    if s.chars().count() <= max_len {
+        return s.to_string();
+    }
+    let mut truncated: String = s.chars().take(max_len.saturating_sub(3)).collect();
+    truncated.push_str("...");
+    truncated
+}
+
+/// Return the container of a record if resolved, otherwise the one of its
+/// task read from `procfs` now.
+pub(super) fn container_of(record: &dyn Record, procfs: &dyn ProcFs) -> ContainerInfo {
+    match record.container() {
+        Some(container) => container.clone(),
+        None => procfs
+            .cgroup(record.stats().tid)
+            .map(|path| ContainerInfo::from_cgroup_path(&path))
+            .unwrap_or_default(),
+    }
+}
+
+pub(super) fn username(uid: u32) -> Option<String> {
+    let mut buf_len = 1024;
+    loop {
+        let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; buf_len];
+        let mut result = ptr::null_mut();
+        let ret =
+            unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+        if ret == libc::ERANGE && buf_len < 1 << 20 {
+            buf_len *= 2;
+            continue;
+        }
+        if ret != 0 || result.is_null() {
+            return None;
+        }
+        let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+        return Some(name.to_string_lossy().into_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Enriched;
+    use crate::{taskstats, AsBuf};
+
+    fn stats(tid: u32, uid: u32) -> TaskStats {
+        let mut raw: taskstats = unsafe { mem::zeroed() };
+        raw.ac_pid = tid;
+        raw.ac_uid = uid;
+        raw.ac_comm[..3].copy_from_slice(&[b'f' as _, b'o' as _, b'o' as _]);
+        TaskStats::from(raw.as_buf())
+    }
+
+    #[test]
+    fn test_comm_header_format() {
+        assert_eq!(
+            "foo (1234)",
+            CommHeaderFormat::new().format(&stats(1234, 0))
+        );
+    }
+
+    #[test]
+    fn test_cmdline_header_format() {
+        let format = |max_len| {
+            CmdlineHeaderFormat::new(max_len).with_procfs(Arc::new(procfs::tests::fixture()))
+        };
+        assert_eq!(
+            "nginx: master process -g daemon off;",
+            format(1024).format(&stats(1234, 0))
+        );
+        assert_eq!("nginx: ma...", format(12).format(&stats(1234, 0)));
+        assert_eq!("[foo]", format(10).format(&stats(9999, 0)));
+    }
+
+    #[test]
+    fn test_user_header_format() {
+        let format = UserHeaderFormat::new().with_lookup(|uid| match uid {
+            0 => Some("root".to_string()),
+            _ => None,
+        });
+        assert_eq!("root (1234)", format.format(&stats(1234, 0)));
+        assert_eq!("1000 (1234)", format.format(&stats(1234, 1000)));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!("abc", truncate("abc", 3));
+        assert_eq!("a...", truncate("abcde", 4));
+    }
+
+    #[test]
+    fn test_container_header_format() {
+        let id = "4f2a3c9b1d0e4f2a3c9b1d0e4f2a3c9b1d0e4f2a3c9b1d0e4f2a3c9b1d0e4f2a";
+        let enriched = Enriched {
+            stats: stats(1234, 0),
+            container: Some(ContainerInfo::from_cgroup_path(&format!(
+                "/system.slice/docker-{}.scope",
+                id
+            ))),
+        };
+        assert_eq!(
+            "4f2a3c9b1d0e (1234)",
+            ContainerHeaderFormat::new().format_record(&enriched)
+        );
+        assert_eq!(
+            format!("/system.slice/docker-{}.scope (1234)", id),
+            CgroupHeaderFormat::new().format_record(&enriched)
+        );
+
+        let fixture = Arc::new(procfs::tests::fixture());
+        let container = ContainerHeaderFormat::new().with_procfs(fixture.clone());
+        let cgroup = CgroupHeaderFormat::new().with_procfs(fixture);
+        assert_eq!("4f2a3c9b1d0e (1234)", container.format(&stats(1234, 0)));
+        assert_eq!("- (1)", container.format(&stats(1, 0)));
+        assert_eq!("/init.scope (1)", cgroup.format(&stats(1, 0)));
+        assert_eq!("- (9999)", container.format(&stats(9999, 0)));
+        assert_eq!("? (9999)", cgroup.format(&stats(9999, 0)));
+    }
+}
@@ -0,0 +1,473 @@
+use super::header::{container_of, username};
+use crate::aggregate::Aggregate;
+use crate::container::Record;
+use crate::procfs::{self, ProcFs};
+use crate::TaskStats;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Value of every label of the series which tasks beyond the cardinality
+/// limit are folded into
+pub const OVERFLOW_LABEL_VALUE: &str = "other";
+
+const DEFAULT_MAX_SERIES: usize = 1000;
+
+/// A label attached to series of `Metrics`. Tasks having the same values of
+/// all labels are aggregated into a single series.
+///
+/// Labels are also used as tags of metrics sent by `StatsdSink`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Label {
+    /// ID of the process a task belongs to
+    Pid,
+    /// Command name
+    Comm,
+    /// Name of the user, or the user ID if it has no name
+    User,
+    /// Path of the cgroup, read from /proc unless resolved along with the record
+    Cgroup,
+    /// ID of the container shortened to 12 characters, empty outside containers
+    Container,
+    /// UID of the Kubernetes pod, empty outside pods
+    Pod,
+}
+
+impl Label {
+    /// Return the name of this label as exposed, which is also the name to select it by.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Label::Pid => "pid",
+            Label::Comm => "comm",
+            Label::User => "user",
+            Label::Cgroup => "cgroup",
+            Label::Container => "container",
+            Label::Pod => "pod",
+        }
+    }
+
+    /// Find a label by its name.
+    pub fn from_name(name: &str) -> Option<Label> {
+        [
+            Label::Pid,
+            Label::Comm,
+            Label::User,
+            Label::Cgroup,
+            Label::Container,
+            Label::Pod,
+        ]
+        .iter()
+        .find(|l| l.name() == name)
+        .copied()
+    }
+
+    /// Take the value out of a record of a task belonging to the process
+    /// `pid`, reading the task through `procfs` if the record lacks it.
+    pub(super) fn value(&self, pid: u32, record: &dyn Record, procfs: &dyn ProcFs) -> String {
+        let ts = record.stats();
+        match self {
+            Label::Pid => pid.to_string(),
+            Label::Comm => ts.comm(),
+            Label::User => {
+                let uid = ts.inner().ac_uid;
+                username(uid).unwrap_or_else(|| uid.to_string())
+            }
+            Label::Cgroup => container_of(record, procfs).cgroup.unwrap_or_default(),
+            Label::Container => container_of(record, procfs)
+                .short_id()
+                .unwrap_or_default()
+                .to_string(),
+            Label::Pod => container_of(record, procfs).pod_uid.unwrap_or_default(),
+        }
+    }
+}
+
+type MetricValue = fn(&TaskStats) -> f64;
+
+/// A counter family with a label distinguishing its values.
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    values: &'static [(&'static str, MetricValue)],
+}
+
+const FAMILIES: &[Family] = &[
+    Family {
+        name: "taskstats_cpu_seconds_total",
+        help: "CPU time consumed by tasks.",
+        label: "mode",
+        values: &[
+            ("user", |ts| ts.cpu.utime_total.as_secs_f64()),
+            ("system", |ts| ts.cpu.stime_total.as_secs_f64()),
+        ],
+    },
+    Family {
+        name: "taskstats_io_bytes_total",
+        help: "Bytes read and written by tasks through syscalls.",
+        label: "direction",
+        values: &[
+            ("read", |ts| ts.io.read_bytes as f64),
+            ("write", |ts| ts.io.write_bytes as f64),
+        ],
+    },
+    Family {
+        name: "taskstats_io_syscalls_total",
+        help: "Read and write syscalls made by tasks.",
+        label: "direction",
+        values: &[
+            ("read", |ts| ts.io.read_syscalls as f64),
+            ("write", |ts| ts.io.write_syscalls as f64),
+        ],
+    },
+    Family {
+        name: "taskstats_blkio_bytes_total",
+        help: "Bytes read from and written to block devices by tasks.",
+        label: "direction",
+        values: &[
+            ("read", |ts| ts.blkio.read_bytes as f64),
+            ("write", |ts| ts.blkio.write_bytes as f64),
+            ("cancelled_write", |ts| {
+                ts.blkio.cancelled_write_bytes as f64
+            }),
+        ],
+    },
+    Family {
+        name: "taskstats_page_faults_total",
+        help: "Page faults of tasks.",
+        label: "type",
+        values: &[
+            ("minor", |ts| ts.memory.minor_faults as f64),
+            ("major", |ts| ts.memory.major_faults as f64),
+        ],
+    },
+    Family {
+        name: "taskstats_context_switches_total",
+        help: "Context switches of tasks.",
+        label: "type",
+        values: &[
+            ("voluntary", |ts| ts.ctx_switches.voluntary as f64),
+            ("nonvoluntary", |ts| ts.ctx_switches.non_voluntary as f64),
+        ],
+    },
+    Family {
+        name: "taskstats_delay_seconds_total",
+        help: "Time tasks spent waiting, by delay accounting category.",
+        label: "delay",
+        values: &[
+            ("cpu", |ts| ts.delays.cpu.delay_total.as_secs_f64()),
+            ("blkio", |ts| ts.delays.blkio.delay_total.as_secs_f64()),
+            ("swapin", |ts| ts.delays.swapin.delay_total.as_secs_f64()),
+            ("freepages", |ts| {
+                ts.delays.freepages.delay_total.as_secs_f64()
+            }),
+        ],
+    },
+    Family {
+        name: "taskstats_delays_total",
+        help: "Count of delays of tasks, by delay accounting category.",
+        label: "delay",
+        values: &[
+            ("cpu", |ts| ts.delays.cpu.count as f64),
+            ("blkio", |ts| ts.delays.blkio.count as f64),
+            ("swapin", |ts| ts.delays.swapin.count as f64),
+            ("freepages", |ts| ts.delays.freepages.count as f64),
+        ],
+    },
+];
+
+/// Metrics of tasks in the Prometheus text exposition format.
+///
+/// Samples of live tasks fed by `add_sample` are summed up into series by the
+/// values of `labels`. Samples are usually cleared by `clear_samples` and
+/// taken again before every `write`.
+///
+/// Exit records fed by `add_exit` are counted into `taskstats_task_exits_total`
+/// and their final values are added to the series the task was last sampled
+/// in, which are kept until the `Metrics` is dropped. So counters don't go
+/// down when tasks exit, except for the moment between a task disappearing
+/// from /proc and its exit record arriving.
+///
+/// Each family has at most `max_series` label sets. Label sets get their own
+/// series in the order they are seen and keep it from then on, so tasks of
+/// label sets seen after reaching the limit are folded into the series labeled
+/// `OVERFLOW_LABEL_VALUE`.
+pub struct Metrics {
+    labels: Vec<Label>,
+    max_series: usize,
+    /// Label sets having their own series
+    series: BTreeSet<Vec<String>>,
+    samples: Aggregate<Vec<String>>,
+    /// Label sets the live tasks were sampled in, by TID
+    sampled: HashMap<u32, Vec<String>>,
+    exits: Aggregate<Vec<String>>,
+    procfs: Arc<dyn ProcFs>,
+}
+
+impl Metrics {
+    /// Create a new `Metrics` labeled by `labels`, at most 1000 series per family.
+    pub fn new(labels: Vec<Label>) -> Self {
+        Self {
+            labels,
+            max_series: DEFAULT_MAX_SERIES,
+            series: BTreeSet::new(),
+            samples: Aggregate::new(),
+            sampled: HashMap::new(),
+            exits: Aggregate::new(),
+            procfs: procfs::system(),
+        }
+    }
+
+    /// Set the maximum number of label sets per family, at least 1.
+    pub fn with_max_series(mut self, max_series: usize) -> Self {
+        self.max_series = max_series.max(1);
+        self
+    }
+
+    /// Read labels of records not enriched through `procfs` instead of /proc.
+    pub fn with_procfs(mut self, procfs: Arc<dyn ProcFs>) -> Self {
+        self.procfs = procfs;
+        self
+    }
+
+    /// Return the label set of the series a task belonging to the process
+    /// `pid` is counted in, giving its own series to a new label set while
+    /// one is left besides the overflow series.
+    fn key(&mut self, pid: u32, record: &dyn Record) -> Vec<String> {
+        let key: Vec<_> = self
+            .labels
+            .iter()
+            .map(|l| l.value(pid, record, &*self.procfs))
+            .collect();
+        if self.series.contains(&key) {
+            return key;
+        }
+        if self.series.len() + 1 >= self.max_series {
+            return vec![OVERFLOW_LABEL_VALUE.to_string(); self.labels.len()];
+        }
+        self.series.insert(key.clone());
+        key
+    }
+
+    /// Drop all samples fed so far, keeping exits.
+    pub fn clear_samples(&mut self) {
+        self.samples.clear();
+        self.sampled.clear();
+    }
+
+    /// Feed the stats of a live task belonging to the process `pid`.
+    pub fn add_sample<R: Record>(&mut self, pid: u32, record: &R) {
+        let key = self.key(pid, record);
+        self.samples.add(key.clone(), record.stats());
+        self.sampled.insert(record.stats().tid, key);
+    }
+
+    /// Count an exit record received from `Client::listen_registered`.
+    ///
+    /// An exited task not sampled since the last `clear_samples` is labeled
+    /// by its record, where the `pid` label is `TaskStats::tgid` or the ID
+    /// of the task itself on kernels not telling it. Per-tgid records are
+    /// ignored.
+    pub fn add_exit<R: Record>(&mut self, record: &R) {
+        let ts = record.stats();
+        if ts.tid == 0 {
+            return;
+        }
+        let key = match self.sampled.remove(&ts.tid) {
+            Some(key) => key,
+            None => self.key(ts.tgid().unwrap_or(ts.tid), record),
+        };
+        self.exits.add(key, ts);
+    }
+
+    /// Write all metrics in the text exposition format.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        // Counters of live tasks along with the final values of exited ones
+        let mut totals = Aggregate::new();
+        for (key, group) in self.samples.iter().chain(&self.exits) {
+            totals.merge(key, group);
+        }
+
+        for family in FAMILIES {
+            writeln!(out, "# HELP {} {}", family.name, family.help)?;
+            writeln!(out, "# TYPE {} counter", family.name)?;
+            for (key, group) in &totals {
+                for (value_label, value) in family.values {
+                    self.write_series(
+                        out,
+                        family.name,
+                        key,
+                        Some((family.label, value_label)),
+                        value(&group.stats),
+                    )?;
+                }
+            }
+        }
+
+        writeln!(out, "# HELP taskstats_tasks Number of live tasks sampled.")?;
+        writeln!(out, "# TYPE taskstats_tasks gauge")?;
+        for (key, group) in &self.samples {
+            self.write_series(out, "taskstats_tasks", key, None, group.tasks as f64)?;
+        }
+
+        writeln!(
+            out,
+            "# HELP taskstats_task_exits_total Number of exited tasks."
+        )?;
+        writeln!(out, "# TYPE taskstats_task_exits_total counter")?;
+        for (key, group) in &self.exits {
+            self.write_series(
+                out,
+                "taskstats_task_exits_total",
+                key,
+                None,
+                group.tasks as f64,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_series<W: Write>(
+        &self,
+        out: &mut W,
+        name: &str,
+        key: &[String],
+        extra: Option<(&str, &str)>,
+        value: f64,
+    ) -> io::Result<()> {
+        let labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|l| l.name())
+            .zip(key.iter().map(|v| v.as_str()))
+            .chain(extra)
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+            .collect();
+        if labels.is_empty() {
+            writeln!(out, "{} {}", name, value)
+        } else {
+            writeln!(out, "{}{{{}}} {}", name, labels.join(","), value)
+        }
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, DelayStat, Delays};
+    use std::time::Duration;
+
+    fn stats(tid: u32, comm: &str, utime: u64) -> TaskStats {
+        TaskStats::builder()
+            .with_tid(tid)
+            .with_comm(comm)
+            .with_cpu(Cpu {
+                utime_total: Duration::from_micros(utime),
+                ..Default::default()
+            })
+            .with_delays(Delays {
+                cpu: DelayStat {
+                    count: 0,
+                    delay_total: Duration::from_millis(500),
+                },
+                ..Default::default()
+            })
+            .build()
+    }
+
+    fn write(metrics: &Metrics) -> String {
+        let mut out = Vec::new();
+        metrics.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write() {
+        let mut metrics = Metrics::new(vec![Label::Comm]);
+        metrics.add_sample(1, &stats(1, "foo", 1_000_000));
+        metrics.add_sample(1, &stats(2, "foo", 500_000));
+        metrics.add_sample(3, &stats(3, "bar", 0));
+        metrics.add_exit(&stats(4, "foo", 0));
+        metrics.add_exit(&stats(0, "", 0));
+        let out = write(&metrics);
+
+        assert!(out.contains("# TYPE taskstats_cpu_seconds_total counter\n"));
+        assert!(out.contains("taskstats_cpu_seconds_total{comm=\"foo\",mode=\"user\"} 1.5\n"));
+        assert!(out.contains("taskstats_delay_seconds_total{comm=\"bar\",delay=\"cpu\"} 0.5\n"));
+        assert!(out.contains("taskstats_tasks{comm=\"foo\"} 2\n"));
+        assert!(out.contains("taskstats_task_exits_total{comm=\"foo\"} 1\n"));
+        assert!(!out.contains("taskstats_task_exits_total{comm=\"\"}"));
+
+        metrics.clear_samples();
+        let out = write(&metrics);
+        assert!(!out.contains("taskstats_tasks{"));
+        assert!(out.contains("taskstats_task_exits_total{comm=\"foo\"} 1\n"));
+    }
+
+    #[test]
+    fn test_exits_keep_counters() {
+        let mut metrics = Metrics::new(vec![Label::Comm]);
+        metrics.add_sample(1, &stats(1, "foo", 1_000_000));
+        metrics.add_sample(1, &stats(2, "foo", 500_000));
+        let out = write(&metrics);
+        assert!(out.contains("taskstats_cpu_seconds_total{comm=\"foo\",mode=\"user\"} 1.5\n"));
+
+        // Thread 2 exits after running 0.5s more and renames itself on the way
+        metrics.add_exit(&stats(2, "bar", 1_000_000));
+        metrics.clear_samples();
+        metrics.add_sample(1, &stats(1, "foo", 1_500_000));
+        let out = write(&metrics);
+        assert!(out.contains("taskstats_cpu_seconds_total{comm=\"foo\",mode=\"user\"} 2.5\n"));
+        assert!(out.contains("taskstats_tasks{comm=\"foo\"} 1\n"));
+        assert!(out.contains("taskstats_task_exits_total{comm=\"foo\"} 1\n"));
+        assert!(!out.contains("comm=\"bar\""));
+
+        metrics.clear_samples();
+        let out = write(&metrics);
+        assert!(out.contains("taskstats_cpu_seconds_total{comm=\"foo\",mode=\"user\"} 1\n"));
+        assert!(!out.contains("taskstats_tasks{"));
+    }
+
+    #[test]
+    fn test_max_series() {
+        let mut metrics = Metrics::new(vec![Label::Pid, Label::Comm]).with_max_series(2);
+        metrics.add_sample(3, &stats(3, "c", 1));
+        metrics.add_sample(2, &stats(2, "b", 2));
+        metrics.add_sample(1, &stats(1, "a", 3));
+        metrics.add_exit(&stats(4, "d", 0));
+        let out = write(&metrics);
+
+        assert!(out.contains("taskstats_tasks{pid=\"3\",comm=\"c\"} 1\n"));
+        assert!(out.contains("taskstats_tasks{pid=\"other\",comm=\"other\"} 2\n"));
+        assert!(!out.contains("comm=\"a\""));
+        assert!(out.contains("taskstats_task_exits_total{pid=\"other\",comm=\"other\"} 1\n"));
+
+        // Label sets keep their series regardless of the order of sampling
+        metrics.clear_samples();
+        metrics.add_sample(1, &stats(1, "a", 3));
+        metrics.add_sample(3, &stats(3, "c", 1));
+        metrics.add_exit(&stats(3, "c", 2));
+        let out = write(&metrics);
+        assert!(out.contains("taskstats_tasks{pid=\"other\",comm=\"other\"} 1\n"));
+        assert!(out.contains("taskstats_task_exits_total{pid=\"3\",comm=\"c\"} 1\n"));
+        assert!(!out.contains("comm=\"a\""));
+    }
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!("a\\\"b\\\\c\\n", escape_label_value("a\"b\\c\n"));
+    }
+
+    #[test]
+    fn test_label_from_name() {
+        assert_eq!(Some(Label::Cgroup), Label::from_name("cgroup"));
+        assert_eq!(None, Label::from_name("nonexistent"));
+    }
+}
@@ -0,0 +1,360 @@
+use super::prometheus::Label;
+use crate::container::Record;
+use crate::procfs::{self, ProcFs};
+use crate::TaskStats;
+use std::collections::HashMap;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Max size of a datagram, which fits in an Ethernet frame along with IP and UDP headers
+const MAX_PACKET_SIZE: usize = 1432;
+
+type Counter = fn(&TaskStats) -> u64;
+
+/// Counters sent as differences between samples
+const COUNTERS: &[(&str, Counter)] = &[
+    ("cpu.user_us", |ts| ts.cpu.utime_total.as_micros() as u64),
+    ("cpu.system_us", |ts| ts.cpu.stime_total.as_micros() as u64),
+    ("io.read_bytes", |ts| ts.io.read_bytes),
+    ("io.write_bytes", |ts| ts.io.write_bytes),
+    ("blkio.read_bytes", |ts| ts.blkio.read_bytes),
+    ("blkio.write_bytes", |ts| ts.blkio.write_bytes),
+    ("ctx_switches.voluntary", |ts| ts.ctx_switches.voluntary),
+    ("ctx_switches.nonvoluntary", |ts| {
+        ts.ctx_switches.non_voluntary
+    }),
+    ("delay.cpu.count", |ts| ts.delays.cpu.count),
+    ("delay.cpu.total_ns", |ts| {
+        ts.delays.cpu.delay_total.as_nanos() as u64
+    }),
+    ("delay.blkio.count", |ts| ts.delays.blkio.count),
+    ("delay.blkio.total_ns", |ts| {
+        ts.delays.blkio.delay_total.as_nanos() as u64
+    }),
+    ("delay.swapin.count", |ts| ts.delays.swapin.count),
+    ("delay.swapin.total_ns", |ts| {
+        ts.delays.swapin.delay_total.as_nanos() as u64
+    }),
+    ("delay.freepages.count", |ts| ts.delays.freepages.count),
+    ("delay.freepages.total_ns", |ts| {
+        ts.delays.freepages.delay_total.as_nanos() as u64
+    }),
+];
+
+/// Sends metrics of tasks to a StatsD server over UDP.
+///
+/// Metrics are tagged in the DogStatsD format (`|#comm:foo,cgroup:/bar`),
+/// which is also understood by Telegraf and statsd_exporter. Tags whose value
+/// can't be resolved, e.g. the cgroup of an exited task, are left out.
+///
+/// For samples of live tasks given to `send_samples`, the followings are sent
+/// as of the previous call:
+/// * counters of increase of CPU time, I/O, context switches and delays, named
+///   like `taskstats.cpu.user_us`, left out if not increased
+/// * gauges of delays in percentage of the elapsed time, named like
+///   `taskstats.delay.cpu.pct`
+///
+/// For an exit record given to `send_exit`, the followings are sent:
+/// * the counters increased since the previous `send_samples`, if the task
+///   was sampled by it
+/// * counter `taskstats.exit.count`
+/// * timers of lifetime, CPU time and each delay in milliseconds, named like
+///   `taskstats.exit.lifetime` and `taskstats.exit.delay.cpu`
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<Label>,
+    /// Tasks sampled by the previous call of `send_samples` along with their processes
+    previous: HashMap<u32, (u32, TaskStats)>,
+    buf: String,
+    procfs: Arc<dyn ProcFs>,
+}
+
+impl StatsdSink {
+    /// Create a new `StatsdSink` sending to `addr`, prefixing metrics by
+    /// `taskstats` and tagging them by comm and cgroup.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address resolved"))?;
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        Ok(Self {
+            socket,
+            prefix: "taskstats".to_string(),
+            tags: vec![Label::Comm, Label::Cgroup],
+            previous: HashMap::new(),
+            buf: String::new(),
+            procfs: procfs::system(),
+        })
+    }
+
+    /// Set the prefix of metric names.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Set the labels of tasks to tag metrics with.
+    pub fn with_tags(mut self, tags: Vec<Label>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Read tags of records not enriched through `procfs` instead of /proc.
+    pub fn with_procfs(mut self, procfs: Arc<dyn ProcFs>) -> Self {
+        self.procfs = procfs;
+        self
+    }
+
+    /// Send metrics of a sampling interval out of stats of live tasks along
+    /// with the IDs of their processes.
+    ///
+    /// Tasks sampled for the first time are only remembered to take
+    /// differences of the next call.
+    pub fn send_samples<R: Record>(&mut self, samples: &[(u32, R)]) -> io::Result<()> {
+        let mut current = HashMap::with_capacity(samples.len());
+        for (pid, record) in samples {
+            let ts = record.stats();
+            current.insert(ts.tid, (*pid, *ts));
+            let prev = match self.previous_of(ts) {
+                Some((_, prev)) => prev,
+                None => continue,
+            };
+            let tags = self.tags(*pid, record);
+            self.push_counters(ts, &prev, &tags)?;
+            let interval = ts.elapsed() - prev.elapsed();
+            let delays = ts.delays - prev.delays;
+            for (name, delta) in [
+                ("delay.cpu.pct", delays.cpu),
+                ("delay.blkio.pct", delays.blkio),
+                ("delay.swapin.pct", delays.swapin),
+                ("delay.freepages.pct", delays.freepages),
+            ] {
+                let value = format!("{:.2}", delta.percent_of(interval));
+                self.push(name, &value, "g", &tags)?;
+            }
+        }
+        self.previous = current;
+        self.flush()
+    }
+
+    /// Send metrics of an exit record received from `Client::listen_registered`.
+    ///
+    /// Per-tgid records are ignored.
+    pub fn send_exit<R: Record>(&mut self, record: &R) -> io::Result<()> {
+        let ts = record.stats();
+        if ts.tid == 0 {
+            return Ok(());
+        }
+        let tags = match self.previous_of(ts) {
+            Some((pid, prev)) => {
+                self.previous.remove(&ts.tid);
+                let tags = self.tags(pid, record);
+                self.push_counters(ts, &prev, &tags)?;
+                tags
+            }
+            None => self.tags(ts.tid, record),
+        };
+        let millis = |d: Duration| format!("{:.3}", d.as_secs_f64() * 1000.0);
+        self.push("exit.count", "1", "c", &tags)?;
+        self.push("exit.lifetime", &millis(ts.elapsed()), "ms", &tags)?;
+        let cpu = ts.cpu.utime_total + ts.cpu.stime_total;
+        self.push("exit.cpu", &millis(cpu), "ms", &tags)?;
+        for (name, delay) in [
+            ("exit.delay.cpu", ts.delays.cpu),
+            ("exit.delay.blkio", ts.delays.blkio),
+            ("exit.delay.swapin", ts.delays.swapin),
+            ("exit.delay.freepages", ts.delays.freepages),
+        ] {
+            self.push(name, &millis(delay.delay_total), "ms", &tags)?;
+        }
+        self.flush()
+    }
+
+    /// Return the previous sample of the task of `ts` along with its process,
+    /// unless the ID has been reused by a new task since then.
+    fn previous_of(&self, ts: &TaskStats) -> Option<(u32, TaskStats)> {
+        self.previous
+            .get(&ts.tid)
+            .filter(|(_, prev)| prev.elapsed() <= ts.elapsed())
+            .copied()
+    }
+
+    /// Buffer the counters increased from `prev` to `ts`
+    fn push_counters(&mut self, ts: &TaskStats, prev: &TaskStats, tags: &str) -> io::Result<()> {
+        for (name, counter) in COUNTERS {
+            let delta = counter(ts).saturating_sub(counter(prev));
+            if delta > 0 {
+                self.push(name, &delta.to_string(), "c", tags)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn tags(&self, pid: u32, record: &dyn Record) -> String {
+        let tags: Vec<_> = self
+            .tags
+            .iter()
+            .map(|l| (l.name(), l.value(pid, record, &*self.procfs)))
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| format!("{}:{}", name, sanitize_tag_value(&value)))
+            .collect();
+        if tags.is_empty() {
+            String::new()
+        } else {
+            format!("|#{}", tags.join(","))
+        }
+    }
+
+    /// Buffer a metric, sending buffered ones first if the datagram would get too large
+    fn push(&mut self, name: &str, value: &str, kind: &str, tags: &str) -> io::Result<()> {
+        let line = format!("{}.{}:{}|{}{}", self.prefix, name, value, kind, tags);
+        if !self.buf.is_empty() && self.buf.len() + 1 + line.len() > MAX_PACKET_SIZE {
+            self.flush()?;
+        }
+        if !self.buf.is_empty() {
+            self.buf.push('\n');
+        }
+        self.buf.push_str(&line);
+        Ok(())
+    }
+
+    /// Send buffered metrics.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let result = self.socket.send(self.buf.as_bytes());
+        self.buf.clear();
+        result.map(|_| ())
+    }
+}
+
+/// Replace characters having meanings in the DogStatsD format
+fn sanitize_tag_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '|' | ',' | '#' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, DelayStat, Delays};
+
+    fn stats(tid: u32, etime: u64, utime: u64, cpu_delay: u64) -> TaskStats {
+        TaskStats::builder()
+            .with_tid(tid)
+            .with_comm("foo")
+            .with_elapsed(Duration::from_micros(etime))
+            .with_cpu(Cpu {
+                utime_total: Duration::from_micros(utime),
+                ..Default::default()
+            })
+            .with_delays(Delays {
+                cpu: DelayStat {
+                    count: cpu_delay / 1000,
+                    delay_total: Duration::from_nanos(cpu_delay),
+                },
+                ..Default::default()
+            })
+            .build()
+    }
+
+    fn listen() -> (UdpSocket, StatsdSink) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let sink = StatsdSink::connect(server.local_addr().unwrap())
+            .unwrap()
+            .with_tags(vec![Label::Pid, Label::Comm]);
+        (server, sink)
+    }
+
+    fn recv(server: &UdpSocket) -> Vec<String> {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let n = server.recv(&mut buf).unwrap();
+        let packet = String::from_utf8(buf[..n].to_vec()).unwrap();
+        packet.lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_send_samples() {
+        let (server, mut sink) = listen();
+        sink.send_samples(&[(10, stats(11, 1_000_000, 100, 0))])
+            .unwrap();
+        sink.send_samples(&[(10, stats(11, 3_000_000, 600, 500_000_000))])
+            .unwrap();
+
+        let lines = recv(&server);
+        assert_eq!(3 + 4, lines.len());
+        assert!(!lines.iter().any(|l| l.starts_with("taskstats.io.")));
+        assert!(lines.contains(&"taskstats.cpu.user_us:500|c|#pid:10,comm:foo".to_string()));
+        assert!(lines.contains(&"taskstats.delay.cpu.count:500000|c|#pid:10,comm:foo".to_string()));
+        assert!(lines.contains(&"taskstats.delay.cpu.pct:25.00|g|#pid:10,comm:foo".to_string()));
+    }
+
+    #[test]
+    fn test_send_exit() {
+        let (server, mut sink) = listen();
+        sink = sink.with_prefix("ts");
+        sink.send_exit(&stats(0, 0, 0, 0)).unwrap();
+        sink.send_exit(&stats(11, 2_500, 1_000, 2_000_000)).unwrap();
+
+        let lines = recv(&server);
+        assert_eq!("ts.exit.count:1|c|#pid:11,comm:foo", lines[0]);
+        assert_eq!("ts.exit.lifetime:2.500|ms|#pid:11,comm:foo", lines[1]);
+        assert_eq!("ts.exit.cpu:1.000|ms|#pid:11,comm:foo", lines[2]);
+        assert_eq!("ts.exit.delay.cpu:2.000|ms|#pid:11,comm:foo", lines[3]);
+    }
+
+    #[test]
+    fn test_send_exit_of_sampled() {
+        let (server, mut sink) = listen();
+        sink.send_samples(&[(10, stats(11, 1_000_000, 100, 0))])
+            .unwrap();
+        sink.send_exit(&stats(11, 2_000_000, 300, 0)).unwrap();
+
+        let lines = recv(&server);
+        assert_eq!("taskstats.cpu.user_us:200|c|#pid:10,comm:foo", lines[0]);
+        assert_eq!("taskstats.exit.count:1|c|#pid:10,comm:foo", lines[1]);
+
+        // The next sample catching the exiting task only remembers it
+        sink.send_samples(&[(10, stats(11, 2_000_000, 300, 0))])
+            .unwrap();
+        sink.send_exit(&stats(12, 0, 0, 0)).unwrap();
+        let lines = recv(&server);
+        assert_eq!("taskstats.exit.count:1|c|#pid:12,comm:foo", lines[0]);
+    }
+
+    #[test]
+    fn test_packet_size() {
+        let (server, mut sink) = listen();
+        for _ in 0..100 {
+            sink.push("exit.count", "1", "c", "").unwrap();
+        }
+        sink.flush().unwrap();
+        let lines = recv(&server);
+        assert!(lines.len() < 100);
+        assert!(lines.join("\n").len() <= MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn test_sanitize_tag_value() {
+        assert_eq!("a_b_c_d", sanitize_tag_value("a|b,c#d"));
+    }
+}
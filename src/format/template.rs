@@ -0,0 +1,239 @@
+use super::column::{Column, REAL_TIME_US, VIRTUAL_TIME_US};
+use thiserror::Error;
+
+/// Error returned when a template refers to a column which doesn't exist.
+#[derive(Debug, Error)]
+#[error("unknown column: {0}")]
+pub struct UnknownColumn(pub String);
+
+/// A placeholder of a template.
+#[derive(Debug, Clone, Copy)]
+pub enum Field {
+    /// The label of a task made by `HeaderFormat`
+    Task,
+    /// A column from the registry
+    Column(&'static Column),
+}
+
+impl Field {
+    fn by_name(name: &str) -> Result<Field, UnknownColumn> {
+        if name == "task" {
+            return Ok(Field::Task);
+        }
+        Column::by_name(name)
+            .map(Field::Column)
+            .ok_or_else(|| UnknownColumn(name.to_string()))
+    }
+
+    /// Title printed in the header of a table unless overridden
+    pub fn title(&self) -> &'static str {
+        match self {
+            Field::Task => "Task",
+            Field::Column(c) => c.title,
+        }
+    }
+}
+
+/// A piece of a template.
+#[derive(Debug, Clone)]
+pub enum Part {
+    /// Text printed as is
+    Literal(String),
+    /// A field with the title printed in the header of a table
+    Field(Field, String),
+}
+
+/// A layout of printing stats, made of fields and literal texts.
+///
+/// When printed as a table by `Printer::print_template_table`, each field
+/// becomes a column and literals are ignored. When printed as text by
+/// `Printer::print_template_text`, the whole template is printed for each
+/// task with fields replaced by values.
+///
+/// The views of `Printer::print_summary_lines`, `print_delay_lines` and
+/// `print_full` are presets of templates: `Template::summary()`,
+/// `Template::delay()` and `Template::full()`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parse a format string like `"%tid %comm %cpu_delay_avg"`.
+    ///
+    /// `%name` is replaced by the column named `name`, or the task label for
+    /// `%task`. A name consists of alphanumerics and underscores. `%%` prints
+    /// a `%`.
+    pub fn parse(format: &str) -> Result<Self, UnknownColumn> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                literal.push('%');
+                continue;
+            }
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            let field = Field::by_name(&name)?;
+            parts.push(Part::Field(field, field.title().to_string()));
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Parse a comma separated list of fields like `ps -o` does, e.g. `"tid,comm=COMMAND"`.
+    ///
+    /// A field can be followed by `=title` to override its title.
+    pub fn from_fields(fields: &str) -> Result<Self, UnknownColumn> {
+        let mut parts = Vec::new();
+        for spec in fields.split(',').filter(|s| !s.is_empty()) {
+            let (name, title) = match spec.find('=') {
+                Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+                None => (spec, None),
+            };
+            let field = Field::by_name(name)?;
+            let title = title.unwrap_or_else(|| field.title()).to_string();
+            parts.push(Part::Field(field, title));
+        }
+        Ok(Template { parts })
+    }
+
+    /// The layout of `Printer::print_summary_lines`
+    pub fn summary() -> Self {
+        Self::from_fields(
+            "task,utime,stime,rss,vmem,read,write,cpu_delay=d:cpu,blkio_delay=d:bio,\
+             swapin_delay=d:swap,freepages_delay=d:reclaim",
+        )
+        .expect("summary template")
+    }
+
+    /// The layout of `Printer::print_delay_lines`
+    pub fn delay() -> Self {
+        Self::from_fields(
+            "task,cpu_delay_avg,blkio_delay_avg,swapin_delay_avg,freepages_delay_avg,\
+             cpu_delay=cpu total,blkio_delay=blkio total,swapin_delay=swapin total,\
+             freepages_delay=reclaim total,cpu_delay_pct=cpu %,blkio_delay_pct=blkio %,\
+             swapin_delay_pct=swapin %,freepages_delay_pct=reclaim %",
+        )
+        .expect("delay template")
+    }
+
+    /// The layout of `Printer::print_full`
+    pub fn full() -> Self {
+        let mut template = Self::parse(FULL_FORMAT).expect("full template");
+        for part in &mut template.parts {
+            if let Part::Field(Field::Column(column), _) = part {
+                match column.name {
+                    "real_time" => *column = &REAL_TIME_US,
+                    "virtual_time" => *column = &VIRTUAL_TIME_US,
+                    _ => {}
+                }
+            }
+        }
+        template
+    }
+
+    /// Return pieces of this template.
+    pub fn parts(&self) -> &[Part] {
+        &self.parts
+    }
+
+    /// Return fields of this template along with their titles.
+    pub fn fields(&self) -> impl Iterator<Item = (&Field, &str)> {
+        self.parts.iter().filter_map(|p| match p {
+            Part::Field(f, title) => Some((f, title.as_str())),
+            Part::Literal(_) => None,
+        })
+    }
+
+    /// Return columns of this template, leaving out `%task`.
+    pub fn columns(&self) -> Vec<&'static Column> {
+        self.fields()
+            .filter_map(|(f, _)| match f {
+                Field::Column(c) => Some(*c),
+                Field::Task => None,
+            })
+            .collect()
+    }
+}
+
+const FULL_FORMAT: &str = "=== %task ===
+--- CPU ---
+User Time (us): %utime
+System Time (us): %stime
+Real Time (us): %real_time
+Virtual Time (us): %virtual_time
+--- Memory ---
+RSS (MB-usec): %rss
+Virtual (MB-usec): %vmem
+Page Faults (minor:major): %minflt:%majflt
+--- IO ---
+Read (bytes): %read
+Write (bytes): %write
+Syscalls (read:write): %read_syscalls:%write_syscalls
+--- Block Device IO ---
+Read (bytes): %blkio_read
+Write (bytes): %blkio_write
+Write Cancelled (bytes): %blkio_cancelled_write
+--- Context Switches ---
+Voluntary:Non-voluntary: %nvcsw:%nivcsw
+--- Delays ---
+CPU Total(nsec)/Count: %cpu_delay/%cpu_count
+BlkIO Total(nsec)/Count: %blkio_delay/%blkio_count
+SwapIn Total(nsec)/Count: %swapin_delay/%swapin_count
+Mem Reclaim Total(nsec)/Count: %freepages_delay/%freepages_count
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let t = Template::parse("%tid: %comm 100%% %cpu_delay_avg").unwrap();
+        let names: Vec<_> = t.columns().iter().map(|c| c.name).collect();
+        assert_eq!(vec!["tid", "comm", "cpu_delay_avg"], names);
+        match &t.parts()[1] {
+            Part::Literal(s) => assert_eq!(": ", s),
+            p => panic!("unexpected part: {:?}", p),
+        }
+        match &t.parts()[3] {
+            Part::Literal(s) => assert_eq!(" 100% ", s),
+            p => panic!("unexpected part: {:?}", p),
+        }
+        assert!(Template::parse("%tid %nonexistent").is_err());
+    }
+
+    #[test]
+    fn test_from_fields() {
+        let t = Template::from_fields("task,tid,comm=COMMAND").unwrap();
+        let titles: Vec<_> = t.fields().map(|(_, title)| title).collect();
+        assert_eq!(vec!["Task", "tid", "COMMAND"], titles);
+        assert_eq!(2, t.columns().len());
+        assert!(Template::from_fields("tid,nonexistent").is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(11, Template::summary().fields().count());
+        assert_eq!(13, Template::delay().fields().count());
+        assert_eq!(26, Template::full().fields().count());
+    }
+}
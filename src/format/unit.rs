@@ -0,0 +1,176 @@
+use super::column::{Column, Unit, Value};
+use crate::TaskStats;
+
+/// The "MB" of MB-usecs integrals, which is a MiB: the kernel divides
+/// `bytes * nsecs` by `1000 * KB`, where the 1000 turns nsecs into usecs
+const MB_USEC_BYTES: u128 = 1 << 20;
+
+/// Style of rendering values having units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnitStyle {
+    /// Raw integers in the unit the kernel reports (e.g. usecs, nsecs, bytes, MB-usecs)
+    #[default]
+    Raw,
+    /// Durations like `12.3ms` and sizes in binary prefixes like `4.1MiB`
+    Human,
+    /// Durations like `12.3ms` and sizes in SI prefixes like `4.3MB`
+    Si,
+}
+
+/// Render the value of `column` taken out of `ts` in the given style.
+///
+/// With styles other than `Raw`, memory usage integrals (MB-usecs) are turned
+/// into the average size over the CPU time of the task, which is the period the
+/// kernel integrates them over.
+pub(crate) fn render(
+    column: &Column,
+    ts: &TaskStats,
+    style: UnitStyle,
+    precision: usize,
+) -> String {
+    let v = match column.value(ts) {
+        Value::Int(v) => v,
+        Value::Float(v) => return format_float(v, column.unit, style, precision),
+        Value::Text(v) => return v,
+    };
+    match (style, column.unit) {
+        (UnitStyle::Raw, _) | (_, Unit::None | Unit::Percent) => v.to_string(),
+        (_, Unit::Micros) => format_nanos(v * 1000, precision),
+        (_, Unit::Nanos) => format_nanos(v, precision),
+        (_, Unit::Bytes) => format_bytes(v, style, precision),
+        (_, Unit::MbUsecs) => {
+            let cpu_us = (ts.cpu.utime_total + ts.cpu.stime_total).as_micros();
+            let avg = (v * MB_USEC_BYTES).checked_div(cpu_us).unwrap_or(0);
+            format_bytes(avg, style, precision)
+        }
+    }
+}
+
+/// Parse a value of the given unit, optionally suffixed by a unit like the
+/// ones rendered by non-raw styles (e.g. `10ms`, `1.5MiB`, `2MB`), into the
+/// raw integer of the unit, or a float for percentages (e.g. `0.5%`).
+pub(crate) fn parse_value(unit: Unit, s: &str) -> Option<Value> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+    if unit == Unit::Percent {
+        if !(suffix.is_empty() || suffix == "%") {
+            return None;
+        }
+        return number.parse().ok().map(Value::Float);
+    }
+    if suffix.is_empty() {
+        return number.parse().ok().map(Value::Int);
+    }
+    let number: f64 = number.parse().ok()?;
+    let scale: f64 = match (unit, suffix) {
+        (Unit::Nanos, "ns") | (Unit::Micros, "us") | (Unit::Bytes, "B") => 1.0,
+        (Unit::Nanos, "us") | (Unit::Micros, "ms") => 1e3,
+        (Unit::Nanos, "ms") | (Unit::Micros, "s") => 1e6,
+        (Unit::Nanos, "s") => 1e9,
+        (Unit::Bytes, "kB") => 1e3,
+        (Unit::Bytes, "MB") => 1e6,
+        (Unit::Bytes, "GB") => 1e9,
+        (Unit::Bytes, "TB") => 1e12,
+        (Unit::Bytes, "KiB") => (1u64 << 10) as f64,
+        (Unit::Bytes, "MiB") => (1u64 << 20) as f64,
+        (Unit::Bytes, "GiB") => (1u64 << 30) as f64,
+        (Unit::Bytes, "TiB") => (1u64 << 40) as f64,
+        _ => return None,
+    };
+    Some(Value::Int((number * scale) as u128))
+}
+
+fn format_float(v: f64, unit: Unit, style: UnitStyle, precision: usize) -> String {
+    match (style, unit) {
+        (UnitStyle::Human | UnitStyle::Si, Unit::Percent) => format!("{:.*}%", precision, v),
+        _ => format!("{:.*}", precision, v),
+    }
+}
+
+pub(super) fn format_nanos(ns: u128, precision: usize) -> String {
+    const UNITS: &[(u128, &str)] = &[(1_000_000_000, "s"), (1_000_000, "ms"), (1_000, "us")];
+    for (scale, suffix) in UNITS {
+        if ns >= *scale {
+            return format!("{:.*}{}", precision, ns as f64 / *scale as f64, suffix);
+        }
+    }
+    format!("{}ns", ns)
+}
+
+fn format_bytes(bytes: u128, style: UnitStyle, precision: usize) -> String {
+    let (base, suffixes): (f64, &[&str]) = match style {
+        UnitStyle::Si => (1000.0, &["kB", "MB", "GB", "TB", "PB"]),
+        _ => (1024.0, &["KiB", "MiB", "GiB", "TiB", "PiB"]),
+    };
+    if (bytes as f64) < base {
+        return format!("{}B", bytes);
+    }
+    let mut v = bytes as f64;
+    let mut suffix = "";
+    for s in suffixes {
+        v /= base;
+        suffix = s;
+        if v < base {
+            break;
+        }
+    }
+    format!("{:.*}{}", precision, v, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_nanos() {
+        assert_eq!("999ns", format_nanos(999, 1));
+        assert_eq!("1.5us", format_nanos(1_500, 1));
+        assert_eq!("12.3ms", format_nanos(12_345_678, 1));
+        assert_eq!("4.10s", format_nanos(4_100_000_000, 2));
+        assert_eq!("120s", format_nanos(120_000_000_000, 0));
+    }
+
+    #[test]
+    fn test_parse_value() {
+        let int = |v| Some(Value::Int(v));
+        assert_eq!(int(123), parse_value(Unit::None, "123"));
+        assert_eq!(int(10_000_000), parse_value(Unit::Nanos, "10ms"));
+        assert_eq!(int(1_500), parse_value(Unit::Micros, "1.5ms"));
+        assert_eq!(int(2_000_000), parse_value(Unit::Micros, "2s"));
+        assert_eq!(int(1536), parse_value(Unit::Bytes, "1.5KiB"));
+        assert_eq!(int(2_000_000), parse_value(Unit::Bytes, "2MB"));
+        assert_eq!(None, parse_value(Unit::None, "2ms"));
+        assert_eq!(None, parse_value(Unit::None, "1.5"));
+        assert_eq!(None, parse_value(Unit::Nanos, "2MB"));
+        assert_eq!(None, parse_value(Unit::Nanos, "ms"));
+        assert_eq!(Some(Value::Float(10.0)), parse_value(Unit::Percent, "10%"));
+        assert_eq!(Some(Value::Float(10.0)), parse_value(Unit::Percent, "10"));
+        assert_eq!(Some(Value::Float(0.5)), parse_value(Unit::Percent, "0.5%"));
+        assert_eq!(None, parse_value(Unit::Percent, "10ms"));
+        assert_eq!(None, parse_value(Unit::Nanos, "10%"));
+    }
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!(
+            "12.35",
+            format_float(12.345, Unit::Percent, UnitStyle::Raw, 2)
+        );
+        assert_eq!(
+            "12.3%",
+            format_float(12.345, Unit::Percent, UnitStyle::Human, 1)
+        );
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!("1023B", format_bytes(1023, UnitStyle::Human, 1));
+        assert_eq!("1.0KiB", format_bytes(1024, UnitStyle::Human, 1));
+        assert_eq!("1.5MiB", format_bytes(1536 * 1024, UnitStyle::Human, 1));
+        assert_eq!("1.6MB", format_bytes(1536 * 1024, UnitStyle::Si, 1));
+        assert_eq!("2048.0PiB", format_bytes(2048 << 50, UnitStyle::Human, 1));
+    }
+}
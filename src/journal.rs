@@ -0,0 +1,618 @@
+use crate::{TaskStats, TASKSTATS_SIZE};
+use log::warn;
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Magic bytes at the head of every segment, which also tell the format version
+const MAGIC: &[u8; 8] = b"TSJOURN1";
+
+/// Extension of segment files
+const SEGMENT_EXTENSION: &str = "tsj";
+
+/// Size of the header of a record: the length and the CRC-32 of the payload
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// Size of the timestamp at the head of a payload
+const TIMESTAMP_SIZE: usize = 8;
+
+/// Payloads larger than this are considered corrupted
+const MAX_PAYLOAD_SIZE: usize = 1 << 16;
+
+/// Size of the buffer initially allocated to read a payload into
+const READ_CHUNK_SIZE: usize = 1 << 16;
+
+/// A record read out of a journal.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    /// Time when the record was appended
+    pub time: SystemTime,
+    pub stats: TaskStats,
+}
+
+/// Appends exit records to a journal, a directory of segment files.
+///
+/// A segment starts with 8 magic bytes followed by records of:
+/// * the length of the payload, u32 in little endian
+/// * the CRC-32 (IEEE) of the payload, u32 in little endian
+/// * the payload: the time of appending in nanoseconds since the UNIX epoch,
+///   u64 in little endian, followed by the raw `taskstats` struct
+///
+/// The raw struct is stored as is in the byte order of the host, so journals
+/// are readable only on hosts of the same byte order.
+///
+/// Segments are named by the time of their creation so that their names sort
+/// in the order of records. The last segment is appended to until it reaches
+/// the size or the age given by `with_max_size` or `with_max_age`, then a new
+/// segment is started. Old segments beyond `with_max_segments` are removed.
+///
+/// A record partially written by a crash is detected by its length or CRC,
+/// ignored by `JournalReader`, and truncated when the journal is opened again.
+/// A corrupted record in the middle of a segment is skipped over by looking
+/// for the next valid record, keeping the records after it.
+pub struct JournalWriter {
+    dir: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    created: SystemTime,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    max_segments: Option<usize>,
+}
+
+impl JournalWriter {
+    /// Open the journal in `dir`, creating the directory if it doesn't exist,
+    /// and continue appending to its last segment.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let (file, size, created) = match segments(&dir)?.pop() {
+            Some(path) => {
+                let created = segment_time(&path).unwrap_or_else(SystemTime::now);
+                let (file, size) = open_segment(&path)?;
+                (file, size, created)
+            }
+            None => create_segment(&dir)?,
+        };
+        Ok(Self {
+            dir,
+            file: BufWriter::new(file),
+            size,
+            created,
+            max_size: None,
+            max_age: None,
+            max_segments: None,
+        })
+    }
+
+    /// Start a new segment once the current one has grown to `bytes`.
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Start a new segment once the current one has been created for `age`.
+    pub fn with_max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Keep at most `n` segments, removing the oldest ones on rotation.
+    pub fn with_max_segments(mut self, n: usize) -> Self {
+        self.max_segments = Some(n.max(1));
+        self
+    }
+
+    /// Append a record stamped by the current time.
+    pub fn append(&mut self, ts: &TaskStats) -> io::Result<()> {
+        self.append_at(ts, SystemTime::now())
+    }
+
+    /// Append a record stamped by `time`.
+    pub fn append_at(&mut self, ts: &TaskStats, time: SystemTime) -> io::Result<()> {
+        if self.needs_rotation() {
+            self.rotate()?;
+        }
+        let nanos = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut payload = Vec::with_capacity(TIMESTAMP_SIZE + TASKSTATS_SIZE);
+        payload.extend_from_slice(&nanos.to_le_bytes());
+        payload.extend_from_slice(&ts.inner_buf);
+
+        write_record(&mut self.file, &payload)?;
+        self.size += (RECORD_HEADER_SIZE + payload.len()) as u64;
+        Ok(())
+    }
+
+    /// Write buffered records to the segment file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Write buffered records and wait for them to reach the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()
+    }
+
+    fn needs_rotation(&self) -> bool {
+        if self.size <= MAGIC.len() as u64 {
+            return false;
+        }
+        let too_large = self.max_size.is_some_and(|max| self.size >= max);
+        let too_old = self.max_age.is_some_and(|max| {
+            SystemTime::now()
+                .duration_since(self.created)
+                .is_ok_and(|age| age >= max)
+        });
+        too_large || too_old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.sync()?;
+        let (file, size, created) = create_segment(&self.dir)?;
+        self.file = BufWriter::new(file);
+        self.size = size;
+        self.created = created;
+        if let Some(max) = self.max_segments {
+            let segments = segments(&self.dir)?;
+            let excess = segments.len().saturating_sub(max);
+            for path in &segments[..excess] {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads records out of a journal written by `JournalWriter`, in the order of appending.
+///
+/// Corrupted records are skipped over with a warning, and reading a segment
+/// stops at a record partially written at its tail, then continues with the
+/// next segment.
+pub struct JournalReader {
+    segments: Vec<PathBuf>,
+    next: usize,
+    current: Option<Records<File>>,
+}
+
+impl JournalReader {
+    /// Open a journal directory, or a single segment file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let segments = if path.is_dir() {
+            segments(path)?
+        } else {
+            vec![path.to_path_buf()]
+        };
+        Ok(Self {
+            segments,
+            next: 0,
+            current: None,
+        })
+    }
+
+    fn open_next(&mut self) -> Option<io::Result<()>> {
+        let path = self.segments.get(self.next)?;
+        self.next += 1;
+        let result = File::open(path).and_then(|file| {
+            let mut reader = BufReader::new(file);
+            let mut magic = [0; MAGIC.len()];
+            reader.read_exact(&mut magic)?;
+            if &magic != MAGIC {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("not a journal segment: {}", path.display()),
+                ));
+            }
+            Ok(Records::new(reader, MAGIC.len() as u64))
+        });
+        Some(result.map(|records| self.current = Some(records)))
+    }
+}
+
+impl Iterator for JournalReader {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(records) = &mut self.current {
+                match records.next() {
+                    Ok(Some((payload, skipped))) => {
+                        if skipped > 0 {
+                            warn!(
+                                "Skipped {} corrupted bytes in {}",
+                                skipped,
+                                self.segments[self.next - 1].display()
+                            );
+                        }
+                        return Some(Ok(decode(&payload)));
+                    }
+                    Ok(None) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+                self.current = None;
+            }
+            if let Err(e) = self.open_next()? {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// List segment files in `dir` in the order of creation.
+pub fn segments(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut segments: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION))
+        .collect();
+    segments.sort();
+    Ok(segments)
+}
+
+fn segment_time(path: &Path) -> Option<SystemTime> {
+    let nanos: u64 = path.file_stem()?.to_str()?.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_nanos(nanos))
+}
+
+fn create_segment(dir: &Path) -> io::Result<(File, u64, SystemTime)> {
+    let mut created = SystemTime::now();
+    loop {
+        let nanos = created
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let path = dir.join(format!("{:020}.{}", nanos, SEGMENT_EXTENSION));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(MAGIC)?;
+                return Ok((file, MAGIC.len() as u64, created));
+            }
+            // Segments rotated within the resolution of the clock
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                created += Duration::from_nanos(1);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Open an existing segment for appending, truncating a partially written
+/// record at its tail.
+fn open_segment(path: &Path) -> io::Result<(File, u64)> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut reader = BufReader::new(&file);
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not a journal segment: {}", path.display()),
+        ));
+    }
+    let mut records = Records::new(reader, MAGIC.len() as u64);
+    while let Some((_, skipped)) = records.next()? {
+        if skipped > 0 {
+            warn!("Skipping {} corrupted bytes in {}", skipped, path.display());
+        }
+    }
+    let valid = records.end;
+    drop(records);
+    if file.metadata()?.len() > valid {
+        warn!(
+            "Truncating a partially written record at {} of {}",
+            valid,
+            path.display()
+        );
+        file.set_len(valid)?;
+    }
+    file.seek(SeekFrom::Start(valid))?;
+    Ok((file, valid))
+}
+
+/// Records read out of a segment one after another, skipping over corrupted
+/// ones by looking for a valid record at every following offset
+struct Records<R> {
+    reader: BufReader<R>,
+    /// Offset of the end of the last valid record
+    end: u64,
+}
+
+impl<R: Read + Seek> Records<R> {
+    /// Read records of `reader` positioned at the offset `start`.
+    fn new(reader: BufReader<R>, start: u64) -> Self {
+        Self { reader, end: start }
+    }
+
+    /// Return the payload of the next valid record along with the number of
+    /// corrupted bytes skipped before it, or None at the end of the segment.
+    fn next(&mut self) -> io::Result<Option<(Vec<u8>, u64)>> {
+        let mut skipped = 0;
+        loop {
+            match read_frame(&mut self.reader, MAX_PAYLOAD_SIZE)? {
+                Frame::Record(payload) => {
+                    self.end += skipped + (RECORD_HEADER_SIZE + payload.len()) as u64;
+                    return Ok(Some((payload, skipped)));
+                }
+                Frame::End => return Ok(None),
+                Frame::Corrupted => {
+                    skipped += 1;
+                    let offset = self.end + skipped;
+                    let position = self.reader.stream_position()?;
+                    self.reader.seek_relative(offset as i64 - position as i64)?;
+                }
+            }
+        }
+    }
+}
+
+/// A frame read out of a segment
+enum Frame {
+    Record(Vec<u8>),
+    /// Not even the header is left
+    End,
+    /// Partially written or corrupted
+    Corrupted,
+}
+
+/// Write a record of `payload` framed by its length and CRC-32.
+pub(crate) fn write_record<W: Write>(out: &mut W, payload: &[u8]) -> io::Result<()> {
+    out.write_all(&(payload.len() as u32).to_le_bytes())?;
+    out.write_all(&crc32(payload).to_le_bytes())?;
+    out.write_all(payload)
+}
+
+/// Read the payload of a record, or None at the end of the segment or a
+/// partially written or corrupted record.
+pub(crate) fn read_record<R: Read>(reader: &mut R, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+    match read_frame(reader, max_len)? {
+        Frame::Record(payload) => Ok(Some(payload)),
+        Frame::End | Frame::Corrupted => Ok(None),
+    }
+}
+
+fn read_frame<R: Read>(reader: &mut R, max_len: usize) -> io::Result<Frame> {
+    let mut header = [0; RECORD_HEADER_SIZE];
+    if !read_full(reader, &mut header)? {
+        return Ok(Frame::End);
+    }
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if !(TIMESTAMP_SIZE..=max_len).contains(&len) {
+        return Ok(Frame::Corrupted);
+    }
+    // Grow the buffer as the payload is read rather than allocating `len` at
+    // once, which may be anything up to `max_len` in a corrupted file
+    let mut payload = Vec::with_capacity(len.min(READ_CHUNK_SIZE));
+    reader.by_ref().take(len as u64).read_to_end(&mut payload)?;
+    if payload.len() < len || crc32(&payload) != crc {
+        return Ok(Frame::Corrupted);
+    }
+    Ok(Frame::Record(payload))
+}
+
+/// Fill `buf`, returning false if the reader reaches its end before
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn decode(payload: &[u8]) -> Entry {
+    let nanos = u64::from_le_bytes(payload[..TIMESTAMP_SIZE].try_into().unwrap());
+    Entry {
+        time: UNIX_EPOCH + Duration::from_nanos(nanos),
+        stats: stats_from_raw(&payload[TIMESTAMP_SIZE..]),
+    }
+}
+
+/// Make stats out of a raw `taskstats` struct written by possibly another
+/// version of the struct, truncating or zero-padding it
+pub(crate) fn stats_from_raw(raw: &[u8]) -> TaskStats {
+    let mut buf = [0u8; TASKSTATS_SIZE];
+    let len = raw.len().min(TASKSTATS_SIZE);
+    buf[..len].copy_from_slice(&raw[..len]);
+    TaskStats::from(&buf[..])
+}
+
+/// CRC-32 of the IEEE 802.3 polynomial, as used by zlib
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DelayStat, Delays};
+
+    fn stats(tid: u32) -> TaskStats {
+        TaskStats::builder()
+            .with_tid(tid)
+            .with_delays(Delays {
+                cpu: DelayStat {
+                    count: 0,
+                    delay_total: Duration::from_micros(tid as u64),
+                },
+                ..Default::default()
+            })
+            .build()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "linux-taskstats-journal-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read_tids(path: &Path) -> Vec<u32> {
+        JournalReader::open(path)
+            .unwrap()
+            .map(|e| e.unwrap().stats.tid)
+            .collect()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_write_read() {
+        let dir = temp_dir("write-read");
+        let mut writer = JournalWriter::open(&dir).unwrap();
+        let time = UNIX_EPOCH + Duration::from_nanos(1_680_000_000_123_456_789);
+        writer.append_at(&stats(1), time).unwrap();
+        writer.append(&stats(2)).unwrap();
+        writer.flush().unwrap();
+
+        let entries: Vec<_> = JournalReader::open(&dir)
+            .unwrap()
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(2, entries.len());
+        assert_eq!(time, entries[0].time);
+        assert_eq!(1, entries[0].stats.tid);
+        assert_eq!(
+            Duration::from_micros(2),
+            entries[1].stats.delays.cpu.delay_total
+        );
+
+        // Reopening continues the last segment
+        drop(writer);
+        let mut writer = JournalWriter::open(&dir).unwrap();
+        writer.append(&stats(3)).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(1, segments(&dir).unwrap().len());
+        assert_eq!(vec![1, 2, 3], read_tids(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = temp_dir("rotation");
+        let record_size = (RECORD_HEADER_SIZE + TIMESTAMP_SIZE + TASKSTATS_SIZE) as u64;
+        let mut writer = JournalWriter::open(&dir)
+            .unwrap()
+            .with_max_size(MAGIC.len() as u64 + record_size * 2)
+            .with_max_segments(2);
+        for tid in 1..=5 {
+            writer.append(&stats(tid)).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let segments = segments(&dir).unwrap();
+        assert_eq!(2, segments.len());
+        assert_eq!(vec![3, 4], read_tids(&segments[0]));
+        assert_eq!(vec![3, 4, 5], read_tids(&dir));
+
+        let mut writer = JournalWriter::open(&dir)
+            .unwrap()
+            .with_max_age(Duration::ZERO);
+        writer.append(&stats(6)).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(3, super::segments(&dir).unwrap().len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_partial_tail() {
+        let dir = temp_dir("partial-tail");
+        let mut writer = JournalWriter::open(&dir).unwrap();
+        writer.append(&stats(1)).unwrap();
+        writer.append(&stats(2)).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        // Simulate a crash in the middle of writing a record
+        let segment = segments(&dir).unwrap().pop().unwrap();
+        let full_len = fs::metadata(&segment).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&[100, 1, 0, 0, 1, 2, 3]).unwrap();
+        drop(file);
+        assert_eq!(vec![1, 2], read_tids(&dir));
+
+        let mut writer = JournalWriter::open(&dir).unwrap();
+        assert_eq!(full_len, fs::metadata(&segment).unwrap().len());
+        writer.append(&stats(3)).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(vec![1, 2, 3], read_tids(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let dir = temp_dir("corrupted");
+        let mut writer = JournalWriter::open(&dir).unwrap();
+        writer.append(&stats(1)).unwrap();
+        writer.append(&stats(2)).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let segment = segments(&dir).unwrap().pop().unwrap();
+        let mut data = fs::read(&segment).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&segment, &data).unwrap();
+        assert_eq!(vec![1], read_tids(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_middle_record() {
+        let dir = temp_dir("corrupted-middle");
+        let mut writer = JournalWriter::open(&dir).unwrap();
+        for tid in 1..=3 {
+            writer.append(&stats(tid)).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let segment = segments(&dir).unwrap().pop().unwrap();
+        let mut data = fs::read(&segment).unwrap();
+        let record_size = RECORD_HEADER_SIZE + TIMESTAMP_SIZE + TASKSTATS_SIZE;
+        data[MAGIC.len() + record_size + RECORD_HEADER_SIZE] ^= 0xff;
+        fs::write(&segment, &data).unwrap();
+        assert_eq!(vec![1, 3], read_tids(&dir));
+
+        // Records after the corrupted one are kept on reopening
+        let mut writer = JournalWriter::open(&dir).unwrap();
+        assert_eq!(data.len() as u64, fs::metadata(&segment).unwrap().len());
+        writer.append(&stats(4)).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(vec![1, 3, 4], read_tids(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_not_a_segment() {
+        let dir = temp_dir("not-a-segment");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("0.tsj"), b"garbage!").unwrap();
+        let mut reader = JournalReader::open(&dir).unwrap();
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+        assert!(JournalWriter::open(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,14 +2,28 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
+pub mod acct;
+pub mod aggregate;
 #[allow(dead_code)]
 mod c_headers;
+pub mod cgroup;
+pub mod container;
+#[cfg(feature = "fake")]
+pub mod fake;
 #[cfg(feature = "format")]
 pub mod format;
+pub mod journal;
 mod model;
 pub(crate) mod netlink;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod procfs;
+pub mod recording;
+pub mod report;
+pub mod tree;
 pub use model::*;
 
+use aggregate::Group;
 pub use c_headers::taskstats;
 use c_headers::{
     __u16, __u32, __u64, __u8, TASKSTATS_CMD_ATTR_DEREGISTER_CPUMASK, TASKSTATS_CMD_ATTR_PID,
@@ -18,8 +32,14 @@ use c_headers::{
     TASKSTATS_TYPE_PID, TASKSTATS_TYPE_STATS, TASKSTATS_TYPE_TGID,
 };
 use log::{debug, warn};
+pub use netlink::Error as NetlinkError;
 use netlink::Netlink;
 use netlink::NlPayload;
+pub use netlink::NlSocket;
+use netlink_sys as nl;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{mem, slice};
 use thiserror::Error;
 
@@ -32,6 +52,9 @@ pub enum Error {
     /// Failed to lookup family ID for taskstats
     #[error("no family id corresponding to taskstats found")]
     NoFamilyId,
+    /// Failed to read processes in a cgroup
+    #[error("failed to read cgroup {}: {1}", .0.display())]
+    Cgroup(PathBuf, #[source] io::Error),
     /// Any unknown error
     #[error("unknown error: {0}")]
     Unknown(String),
@@ -40,8 +63,11 @@ pub enum Error {
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Interface to access kernel taskstats API through the netlink socket.
-pub struct Client {
-    netlink: Netlink,
+///
+/// The socket is replaceable by any `NlSocket` for testing, e.g. by
+/// `fake::FakeKernel` with the `fake` feature.
+pub struct Client<S: NlSocket = nl::Socket> {
+    netlink: Netlink<S>,
     ts_family_id: u16,
 }
 
@@ -52,7 +78,54 @@ impl Client {
     /// * when netlink socket initialization failed
     /// * when kernel doesn't offer family id for taskstats
     pub fn open() -> Result<Self> {
-        let netlink = Netlink::open()?;
+        Self::with_netlink(Netlink::open()?)
+    }
+
+    /// Set receiver buffer size in bytes (SO_RCVBUF socket option, see socket(7))
+    ///
+    /// # Arguments
+    /// * `payload` - buffer size in bytes. The kernel doubles this value
+    ///   (to allow space for bookkeeping overhead). The default value is set by the
+    ///   /proc/sys/net/core/rmem_default file, and the maximum allowed value is set by the
+    ///   /proc/sys/net/core/rmem_max file. The minimum (doubled) value for this option is 256.
+    pub fn set_rx_buf_sz<T>(&self, payload: T) -> Result<()> {
+        self.netlink
+            .set_rx_buf_sz(payload)
+            .map_err(|err| err.into())
+    }
+
+    /// Get receiver buffer size in bytes (SO_RCVBUF socket option, see socket(7))
+    ///
+    /// # Return
+    /// * `usize` buffer size in bytes.
+    ///   Kernel returns doubled value, that have been set using [set_rx_buf_sz]
+    pub fn get_rx_buf_sz(&self) -> Result<usize> {
+        self.netlink.get_rx_buf_sz().map_err(|err| err.into())
+    }
+
+    /// Set timeout of receiving messages (SO_RCVTIMEO socket option, see socket(7))
+    ///
+    /// Once set, `listen_registered` fails with `io::ErrorKind::WouldBlock` in
+    /// `NetlinkError::SocketIo` when no exit record arrives within `timeout`.
+    /// `None` makes it wait forever, which is the default.
+    pub fn set_rx_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.netlink
+            .set_rx_timeout(timeout)
+            .map_err(|err| err.into())
+    }
+}
+
+impl<S: NlSocket> Client<S> {
+    /// Create a new instance of `Client` communicating through `sock` with a
+    /// peer at `addr`, e.g. a fake of the kernel at unit testing
+    ///
+    /// # Errors
+    /// * when the peer doesn't offer family id for taskstats
+    pub fn with_socket(sock: S, addr: S::Addr) -> Result<Self> {
+        Self::with_netlink(Netlink::new(sock, addr))
+    }
+
+    fn with_netlink(netlink: Netlink<S>) -> Result<Self> {
         let ts_family_id = Self::lookup_family_id(&netlink)?;
         debug!("Found taskstats family id: {}", ts_family_id);
         Ok(Self {
@@ -61,7 +134,7 @@ impl Client {
         })
     }
 
-    fn lookup_family_id(netlink: &Netlink) -> Result<u16> {
+    fn lookup_family_id(netlink: &Netlink<S>) -> Result<u16> {
         netlink.send_cmd(
             libc::GENL_ID_CTRL as u16,
             libc::CTRL_CMD_GETFAMILY as u8,
@@ -159,6 +232,55 @@ impl Client {
         ))
     }
 
+    /// Obtain taskstats summed up over the processes in a cgroup (e.g. a container)
+    ///
+    /// # Arguments
+    /// * `path` - Path of the cgroup directory, e.g. `/sys/fs/cgroup/system.slice/nginx.service`
+    ///   on cgroup v2 or `/sys/fs/cgroup/cpu/docker/<id>` on cgroup v1
+    /// * `recursive` - Whether to include processes in the descendant cgroups
+    ///
+    /// # Return
+    /// * `Group` of `tgid_stats` of the processes listed in `cgroup.procs`, with the number of
+    ///   processes in `tasks`. Delay counts and totals are summed up separately so that
+    ///   `DelayStat::average` is the average of all delays in the cgroup. Elapsed times are
+    ///   summed up too, see `Group` for the other fields. Processes which exit before their
+    ///   stats are obtained are skipped, and an empty cgroup results in all zero stats.
+    ///
+    /// The kernel fills replies of thread groups only with delay accounting (delays and
+    /// `Cpu::real_time_total`/`virtual_time_total`), `ac_etime` and context switches, so user
+    /// and system CPU times, memory, I/O and block I/O of the returned `Group` are all zero.
+    ///
+    /// # Errors
+    /// * when `cgroup.procs` couldn't be read
+    /// * when netlink socket failed
+    /// * when kernel responded error other than `ESRCH` for a process
+    /// * when the returned data couldn't be interpreted
+    pub fn cgroup_task_stats<P: AsRef<Path>>(&self, path: P, recursive: bool) -> Result<Group> {
+        let path = path.as_ref();
+        let tgids =
+            cgroup::procs(path, recursive).map_err(|e| Error::Cgroup(path.to_path_buf(), e))?;
+        let mut group = Group {
+            tasks: 0,
+            stats: TaskStats::default(),
+        };
+        for tgid in tgids {
+            let ts = match self.tgid_stats(tgid) {
+                Ok(ts) => ts,
+                Err(Error::Netlink(netlink::Error::ErrorResponse(libc::ESRCH))) => {
+                    debug!("Skipping process {} which has gone", tgid);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if group.tasks == 0 {
+                group = Group::new(&ts);
+            } else {
+                group.add(&ts);
+            }
+        }
+        Ok(group)
+    }
+
     /// Register listener with the specific cpumask
     ///
     /// # Arguments
@@ -228,28 +350,6 @@ impl Client {
         ))
     }
 
-    /// Set receiver buffer size in bytes (SO_RCVBUF socket option, see socket(7))
-    ///
-    /// # Arguments
-    /// * `payload` - buffer size in bytes. The kernel doubles this value
-    ///   (to allow space for bookkeeping overhead). The default value is set by the
-    ///   /proc/sys/net/core/rmem_default file, and the maximum allowed value is set by the
-    ///   /proc/sys/net/core/rmem_max file. The minimum (doubled) value for this option is 256.
-    pub fn set_rx_buf_sz<T>(&self, payload: T) -> Result<()> {
-        self.netlink
-            .set_rx_buf_sz(payload)
-            .map_err(|err| err.into())
-    }
-
-    /// Get receiver buffer size in bytes (SO_RCVBUF socket option, see socket(7))
-    ///
-    /// # Return
-    /// * `usize` buffer size in bytes.
-    ///   Kernel returns doubled value, that have been set using [set_rx_buf_sz]
-    pub fn get_rx_buf_sz(&self) -> Result<usize> {
-        self.netlink.get_rx_buf_sz().map_err(|err| err.into())
-    }
-
     pub fn send(&self, taskstats_cmd: u16, data: &[u8]) -> Result<()> {
         self.netlink.send_cmd(
             self.ts_family_id,
@@ -304,4 +404,21 @@ mod tests {
         assert!(ts.delays.cpu.delay_total.as_nanos() > 0);
         assert!(ts.cpu.virtual_time_total.as_nanos() > 0);
     }
+
+    #[cfg(test_priv)]
+    #[test]
+    fn test_cgroup_task_stats() {
+        let client = Client::open().unwrap();
+        let content = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        let path = content
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .expect("cgroup v2");
+        let group = client
+            .cgroup_task_stats(format!("/sys/fs/cgroup{}", path), false)
+            .unwrap();
+
+        assert!(group.tasks >= 1);
+        assert!(group.stats.cpu.virtual_time_total.as_nanos() > 0);
+    }
 }
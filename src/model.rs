@@ -1,6 +1,10 @@
 use crate::c_headers;
-use crate::taskstats;
-use std::mem;
+use crate::{taskstats, AsBuf};
+use std::convert::TryInto;
+use std::iter::Sum;
+use std::mem::{self, MaybeUninit};
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::ptr;
 use std::time::Duration;
 
 // https://stackoverflow.com/questions/53619695/calculating-maximum-value-of-a-set-of-constant-expressions-at-compile-time
@@ -13,6 +17,48 @@ pub const TASKSTATS_SIZE: usize = const_max(
     mem::size_of::<c_headers::taskstats>(),
 );
 
+/// Delay accounting appended to `struct taskstats` after `freepages_delay_total`
+/// by later versions: thrashing (v9), compact (v11), wpcopy (v13) and irq
+/// (v14), as offsets of each `*_count` followed by `*_delay_total` from the end
+/// of `freepages_delay_total`. They're accessed by offset as the headers at
+/// build time may predate them.
+const EXTENDED_DELAYS: &[usize] = &[0, 24, 72, 88];
+
+/// Offset of `ac_tgid` (v12) from the end of `freepages_delay_total`
+const TGID_OFFSET: usize = 40;
+
+/// Return the offset of `ac_tgid` in `struct taskstats` if it has the field
+fn tgid_field() -> Option<usize> {
+    let offset = extended_base() + TGID_OFFSET;
+    Some(offset).filter(|offset| offset + mem::size_of::<u32>() <= TASKSTATS_SIZE)
+}
+
+/// Return the offset of the end of `freepages_delay_total` in `struct taskstats`
+fn extended_base() -> usize {
+    let raw = MaybeUninit::<taskstats>::uninit();
+    let base = raw.as_ptr();
+    let field = unsafe { ptr::addr_of!((*base).freepages_delay_total) };
+    field as usize - base as usize + mem::size_of::<u64>()
+}
+
+/// Return offsets of all `*_count` and `*_delay_total` fields of extended
+/// delays which the raw struct covers
+fn extended_delay_fields() -> impl Iterator<Item = usize> {
+    let base = extended_base();
+    EXTENDED_DELAYS
+        .iter()
+        .flat_map(move |offset| [base + offset, base + offset + mem::size_of::<u64>()])
+        .filter(|offset| offset + mem::size_of::<u64>() <= TASKSTATS_SIZE)
+}
+
+fn read_u64(buf: &[u8; TASKSTATS_SIZE], offset: usize) -> u64 {
+    u64::from_ne_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u64(buf: &mut [u8; TASKSTATS_SIZE], offset: usize, value: u64) {
+    buf[offset..offset + 8].copy_from_slice(&value.to_ne_bytes());
+}
+
 /// The taskstats representation for a task.
 /// This struct remaps commonly used `struct taskstats` fields for primarily:
 /// * Access values with rust's primitive types
@@ -22,8 +68,37 @@ pub const TASKSTATS_SIZE: usize = const_max(
 /// There are more (but may not much interested) fields in the original
 /// `struct taskstats` and they are accessible through obtaining the original
 /// struct by `TaskStats#inner()`.
+///
+/// With the `serde` feature, `TaskStats` and all its member types implement
+/// `Serialize` and `Deserialize`. The schema is a struct having the fields
+/// named as in this struct, with `Duration`s represented as serde's
+/// `{secs, nanos}` struct, plus `raw` holding the bytes of the original
+/// `struct taskstats`.
+/// On deserialization, only `raw` is read and the remapped fields are derived
+/// from it as if received from the kernel, so that the two never disagree.
+/// A `raw` shorter or longer than the local `struct taskstats` (e.g. taken on
+/// a different kernel version) is zero-padded or truncated.
+/// `raw` is in the byte order of the host which serialized it, hence it's not
+/// a portable schema: stats can only be deserialized on hosts of the same byte
+/// order, while consumers on others should read the remapped fields instead.
+///
+/// `TaskStats` and all its member types implement `Add`, `Sub`, `AddAssign`,
+/// `SubAssign` and `Sum`, e.g. to sum up threads or to take the difference of
+/// two samples. The operators saturate at the maximum or zero, while
+/// `checked_add` and `checked_sub` return `None` instead. On `TaskStats`:
+/// * Counters, including delays and the elapsed time (`ac_etime`), are added
+///   up or subtracted both in the remapped fields and in the raw struct. Delays
+///   which aren't remapped (thrashing, compact, wpcopy and irq) are combined
+///   in the raw struct as far as it has them
+/// * High-watermarks (`hiwater_rss` and `hiwater_vm`) aren't additive: a sum
+///   takes the larger one and a difference keeps the one of the left-hand side
+/// * The other fields, e.g. `tid`, pid, comm, uid and the begin time, are of
+///   the left-hand side
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "raw_serde::Raw"))]
 pub struct TaskStats {
+    #[cfg_attr(feature = "serde", serde(rename = "raw", with = "raw_serde"))]
     pub(crate) inner_buf: [u8; TASKSTATS_SIZE],
     /// The target task ID
     pub tid: u32,
@@ -42,7 +117,8 @@ pub struct TaskStats {
 }
 
 /// Staticstics related to CPU time
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cpu {
     /// User CPU time
     pub utime_total: Duration,
@@ -55,7 +131,8 @@ pub struct Cpu {
 }
 
 /// Statistics related to memory, vm
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Memory {
     /// Accumulated RSS usage in duration of a task, in MBytes-usecs
     pub rss_total: u64,
@@ -68,7 +145,8 @@ pub struct Memory {
 }
 
 /// Staticstics related to I/O at syscall surface
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Io {
     /// Bytes read
     pub read_bytes: u64,
@@ -81,7 +159,8 @@ pub struct Io {
 }
 
 /// Statistics related to I/O at block device level
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BlkIo {
     /// Bytes read
     pub read_bytes: u64,
@@ -92,7 +171,8 @@ pub struct BlkIo {
 }
 
 /// Statistics related to context switches
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ContextSwitches {
     /// Count of voluntary context switches
     pub voluntary: u64,
@@ -101,7 +181,8 @@ pub struct ContextSwitches {
 }
 
 /// Statistics related to scheduling delay (delay accounting)
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Delays {
     /// Delay waiting for cpu, while runnable
     pub cpu: DelayStat,
@@ -113,7 +194,8 @@ pub struct Delays {
     pub freepages: DelayStat,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DelayStat {
     /// Number of delay values recorded
     pub count: u64,
@@ -121,6 +203,112 @@ pub struct DelayStat {
     pub delay_total: Duration,
 }
 
+/// Delays in percentage of a period, see `Delays::percent_of`
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DelayPercents {
+    pub cpu: f64,
+    pub blkio: f64,
+    pub swapin: f64,
+    pub freepages: f64,
+}
+
+impl DelayStat {
+    /// Return the average delay, or zero if no delay is recorded.
+    pub fn average(&self) -> Duration {
+        Duration::from_nanos((self.delay_total.as_nanos() / self.count.max(1) as u128) as u64)
+    }
+
+    /// Return the total delay in percentage of `period`, or 0 for an empty `period`.
+    ///
+    /// As a task can be delayed by multiple threads at once in aggregated stats,
+    /// the result can exceed 100.
+    pub fn percent_of(&self, period: Duration) -> f64 {
+        if period.is_zero() {
+            return 0.0;
+        }
+        self.delay_total.as_secs_f64() / period.as_secs_f64() * 100.0
+    }
+}
+
+impl Delays {
+    /// Return all delays in percentage of `period`.
+    ///
+    /// `period` is typically the elapsed time of the task (`TaskStats::elapsed`)
+    /// or the sampling interval when these delays are differences of two samples.
+    pub fn percent_of(&self, period: Duration) -> DelayPercents {
+        DelayPercents {
+            cpu: self.cpu.percent_of(period),
+            blkio: self.blkio.percent_of(period),
+            swapin: self.swapin.percent_of(period),
+            freepages: self.freepages.percent_of(period),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod raw_serde {
+    use super::{TaskStats, TASKSTATS_SIZE};
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    /// The part of serialized `TaskStats` which it's deserialized from
+    #[derive(serde::Deserialize)]
+    pub struct Raw {
+        #[serde(with = "self")]
+        raw: [u8; TASKSTATS_SIZE],
+    }
+
+    impl From<Raw> for TaskStats {
+        fn from(raw: Raw) -> Self {
+            TaskStats::from(&raw.raw[..])
+        }
+    }
+
+    pub fn serialize<S: Serializer>(
+        buf: &[u8; TASKSTATS_SIZE],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(buf)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u8; TASKSTATS_SIZE], D::Error> {
+        deserializer.deserialize_bytes(RawVisitor)
+    }
+
+    struct RawVisitor;
+
+    impl<'de> Visitor<'de> for RawVisitor {
+        type Value = [u8; TASKSTATS_SIZE];
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("bytes of struct taskstats")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            let mut buf = [0u8; TASKSTATS_SIZE];
+            let len = v.len().min(TASKSTATS_SIZE);
+            buf[..len].copy_from_slice(&v[..len]);
+            Ok(buf)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut buf = [0u8; TASKSTATS_SIZE];
+            let mut i = 0;
+            while let Some(b) = seq.next_element::<u8>()? {
+                if i < TASKSTATS_SIZE {
+                    buf[i] = b;
+                }
+                i += 1;
+            }
+            Ok(buf)
+        }
+    }
+}
+
 impl From<&[u8]> for TaskStats {
     fn from(buf: &[u8]) -> Self {
         let mut inner_buf = [0u8; TASKSTATS_SIZE];
@@ -189,4 +377,727 @@ impl TaskStats {
     pub fn inner(&self) -> &taskstats {
         unsafe { &*(self.inner_buf.as_ptr() as *const _ as *const taskstats) }
     }
+
+    /// Return a builder of stats from scratch, e.g. for tests and synthetic data.
+    pub fn builder() -> TaskStatsBuilder {
+        TaskStatsBuilder::new()
+    }
+
+    /// Return the elapsed time since the task started (`ac_etime`).
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_micros(self.inner().ac_etime)
+    }
+
+    /// Return the ID of the process which the task belongs to (`ac_tgid`).
+    ///
+    /// It's `None` on kernels older than taskstats version 12 which don't
+    /// report it, and when built against headers older than that.
+    pub fn tgid(&self) -> Option<u32> {
+        if self.inner().version < 12 {
+            return None;
+        }
+        let offset = tgid_field()?;
+        let tgid = u32::from_ne_bytes(self.inner_buf[offset..offset + 4].try_into().unwrap());
+        Some(tgid).filter(|&tgid| tgid != 0)
+    }
+
+    /// Return the command name of the task.
+    pub fn comm(&self) -> String {
+        let comm = &self.inner().ac_comm;
+        let len = comm.iter().position(|&c| c == 0).unwrap_or(comm.len());
+        let bytes: Vec<u8> = comm[..len].iter().map(|&c| c as u8).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    pub(crate) fn inner_mut(&mut self) -> &mut taskstats {
+        unsafe { &mut *(self.inner_buf.as_mut_ptr() as *mut _ as *mut taskstats) }
+    }
+
+    /// Add up counters of `other` into this stats like `+=`, but keeping the
+    /// elapsed time of `self`, e.g. for the total of a process tree which
+    /// lives as long as its root.
+    pub(crate) fn accumulate(&mut self, other: &TaskStats) {
+        let etime = self.inner().ac_etime;
+        *self += *other;
+        self.inner_mut().ac_etime = etime;
+    }
+
+    /// Add up stats, returning `None` on overflow of any counter.
+    ///
+    /// See `TaskStats` for how each field is added up.
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        let mut overflow = false;
+        let inner_buf = self.combine_raw(
+            &rhs,
+            |a, b| {
+                a.checked_add(b).unwrap_or_else(|| {
+                    overflow = true;
+                    0
+                })
+            },
+            u64::max,
+        );
+        let remapped = self.remapped().checked_add(rhs.remapped())?;
+        if overflow {
+            return None;
+        }
+        Some(Self::from_parts(self.tid, remapped, inner_buf))
+    }
+
+    /// Subtract stats, returning `None` if any counter of `rhs` is larger.
+    ///
+    /// See `TaskStats` for how each field is subtracted.
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        let mut overflow = false;
+        let inner_buf = self.combine_raw(
+            &rhs,
+            |a, b| {
+                a.checked_sub(b).unwrap_or_else(|| {
+                    overflow = true;
+                    0
+                })
+            },
+            |a, _| a,
+        );
+        let remapped = self.remapped().checked_sub(rhs.remapped())?;
+        if overflow {
+            return None;
+        }
+        Some(Self::from_parts(self.tid, remapped, inner_buf))
+    }
+
+    /// Add up stats, with counters saturating at their maximum.
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        let inner_buf = self.combine_raw(&rhs, u64::saturating_add, u64::max);
+        let remapped = self.remapped().saturating_add(rhs.remapped());
+        Self::from_parts(self.tid, remapped, inner_buf)
+    }
+
+    /// Subtract stats, with counters saturating at zero.
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        let inner_buf = self.combine_raw(&rhs, u64::saturating_sub, |a, _| a);
+        let remapped = self.remapped().saturating_sub(rhs.remapped());
+        Self::from_parts(self.tid, remapped, inner_buf)
+    }
+
+    fn remapped(&self) -> Remapped {
+        Remapped {
+            cpu: self.cpu,
+            memory: self.memory,
+            io: self.io,
+            blkio: self.blkio,
+            ctx_switches: self.ctx_switches,
+            delays: self.delays,
+        }
+    }
+
+    fn from_parts(tid: u32, remapped: Remapped, inner_buf: [u8; TASKSTATS_SIZE]) -> Self {
+        TaskStats {
+            inner_buf,
+            tid,
+            cpu: remapped.cpu,
+            memory: remapped.memory,
+            io: remapped.io,
+            blkio: remapped.blkio,
+            ctx_switches: remapped.ctx_switches,
+            delays: remapped.delays,
+        }
+    }
+
+    /// Combine counters, including extended delays, and the elapsed time of the
+    /// raw structs of `self` and `rhs` by `op` and high-watermarks by `hiwater`,
+    /// keeping the other fields of `self`.
+    fn combine_raw(
+        &self,
+        rhs: &TaskStats,
+        mut op: impl FnMut(u64, u64) -> u64,
+        hiwater: impl Fn(u64, u64) -> u64,
+    ) -> [u8; TASKSTATS_SIZE] {
+        let mut inner_buf = self.inner_buf;
+        let ts = unsafe { &mut *(inner_buf.as_mut_ptr() as *mut _ as *mut taskstats) };
+        let o = rhs.inner();
+        ts.ac_etime = op(ts.ac_etime, o.ac_etime);
+        ts.cpu_count = op(ts.cpu_count, o.cpu_count);
+        ts.cpu_delay_total = op(ts.cpu_delay_total, o.cpu_delay_total);
+        ts.blkio_count = op(ts.blkio_count, o.blkio_count);
+        ts.blkio_delay_total = op(ts.blkio_delay_total, o.blkio_delay_total);
+        ts.swapin_count = op(ts.swapin_count, o.swapin_count);
+        ts.swapin_delay_total = op(ts.swapin_delay_total, o.swapin_delay_total);
+        ts.freepages_count = op(ts.freepages_count, o.freepages_count);
+        ts.freepages_delay_total = op(ts.freepages_delay_total, o.freepages_delay_total);
+        ts.cpu_run_real_total = op(ts.cpu_run_real_total, o.cpu_run_real_total);
+        ts.cpu_run_virtual_total = op(ts.cpu_run_virtual_total, o.cpu_run_virtual_total);
+        ts.cpu_scaled_run_real_total =
+            op(ts.cpu_scaled_run_real_total, o.cpu_scaled_run_real_total);
+        ts.ac_utime = op(ts.ac_utime, o.ac_utime);
+        ts.ac_stime = op(ts.ac_stime, o.ac_stime);
+        ts.ac_utimescaled = op(ts.ac_utimescaled, o.ac_utimescaled);
+        ts.ac_stimescaled = op(ts.ac_stimescaled, o.ac_stimescaled);
+        ts.ac_minflt = op(ts.ac_minflt, o.ac_minflt);
+        ts.ac_majflt = op(ts.ac_majflt, o.ac_majflt);
+        ts.coremem = op(ts.coremem, o.coremem);
+        ts.virtmem = op(ts.virtmem, o.virtmem);
+        ts.hiwater_rss = hiwater(ts.hiwater_rss, o.hiwater_rss);
+        ts.hiwater_vm = hiwater(ts.hiwater_vm, o.hiwater_vm);
+        ts.read_char = op(ts.read_char, o.read_char);
+        ts.write_char = op(ts.write_char, o.write_char);
+        ts.read_syscalls = op(ts.read_syscalls, o.read_syscalls);
+        ts.write_syscalls = op(ts.write_syscalls, o.write_syscalls);
+        ts.read_bytes = op(ts.read_bytes, o.read_bytes);
+        ts.write_bytes = op(ts.write_bytes, o.write_bytes);
+        ts.cancelled_write_bytes = op(ts.cancelled_write_bytes, o.cancelled_write_bytes);
+        ts.nvcsw = op(ts.nvcsw, o.nvcsw);
+        ts.nivcsw = op(ts.nivcsw, o.nivcsw);
+        for offset in extended_delay_fields() {
+            let value = op(
+                read_u64(&inner_buf, offset),
+                read_u64(&rhs.inner_buf, offset),
+            );
+            write_u64(&mut inner_buf, offset, value);
+        }
+        inner_buf
+    }
+}
+
+/// Builds `TaskStats` by setting fields of the raw `struct taskstats`, from
+/// which the remapped fields are derived as if received from the kernel.
+///
+/// Fields not set are zero. Durations are truncated to the unit of the raw
+/// field: microseconds for the elapsed, user and system CPU times, and
+/// nanoseconds for the others.
+///
+/// ```
+/// use linux_taskstats::{Cpu, TaskStats};
+/// use std::time::Duration;
+///
+/// let ts = TaskStats::builder()
+///     .with_tid(1234)
+///     .with_comm("nginx")
+///     .with_cpu(Cpu {
+///         utime_total: Duration::from_millis(20),
+///         ..Default::default()
+///     })
+///     .build();
+/// assert_eq!("nginx", ts.comm());
+/// assert_eq!(20_000, ts.inner().ac_utime);
+/// ```
+#[derive(Clone)]
+pub struct TaskStatsBuilder {
+    raw: taskstats,
+}
+
+impl Default for TaskStatsBuilder {
+    fn default() -> Self {
+        let mut raw: taskstats = unsafe { mem::zeroed() };
+        raw.version = c_headers::TASKSTATS_VERSION as u16;
+        Self { raw }
+    }
+}
+
+impl TaskStatsBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the task ID (`ac_pid`).
+    pub fn with_tid(mut self, tid: u32) -> Self {
+        self.raw.ac_pid = tid;
+        self
+    }
+
+    /// Set the parent process ID (`ac_ppid`).
+    pub fn with_ppid(mut self, ppid: u32) -> Self {
+        self.raw.ac_ppid = ppid;
+        self
+    }
+
+    /// Set the user ID (`ac_uid`).
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.raw.ac_uid = uid;
+        self
+    }
+
+    /// Set the group ID (`ac_gid`).
+    pub fn with_gid(mut self, gid: u32) -> Self {
+        self.raw.ac_gid = gid;
+        self
+    }
+
+    /// Set the ID of the process (`ac_tgid`), which is ignored when built
+    /// against headers older than taskstats version 12 lacking it.
+    pub fn with_tgid(mut self, tgid: u32) -> Self {
+        if let Some(offset) = tgid_field() {
+            self.raw.as_buf_mut()[offset..offset + 4].copy_from_slice(&tgid.to_ne_bytes());
+        }
+        self
+    }
+
+    /// Set the command name (`ac_comm`), truncated to leave the terminating NUL.
+    pub fn with_comm(mut self, comm: &str) -> Self {
+        let len = comm.len().min(self.raw.ac_comm.len() - 1);
+        for (i, dst) in self.raw.ac_comm.iter_mut().enumerate() {
+            *dst = if i < len { comm.as_bytes()[i] as _ } else { 0 };
+        }
+        self
+    }
+
+    /// Set the elapsed time since the task started (`ac_etime`).
+    pub fn with_elapsed(mut self, elapsed: Duration) -> Self {
+        self.raw.ac_etime = elapsed.as_micros() as u64;
+        self
+    }
+
+    pub fn with_cpu(mut self, cpu: Cpu) -> Self {
+        self.raw.ac_utime = cpu.utime_total.as_micros() as u64;
+        self.raw.ac_stime = cpu.stime_total.as_micros() as u64;
+        self.raw.cpu_run_real_total = cpu.real_time_total.as_nanos() as u64;
+        self.raw.cpu_run_virtual_total = cpu.virtual_time_total.as_nanos() as u64;
+        self
+    }
+
+    pub fn with_memory(mut self, memory: Memory) -> Self {
+        self.raw.coremem = memory.rss_total;
+        self.raw.virtmem = memory.virt_total;
+        self.raw.ac_minflt = memory.minor_faults;
+        self.raw.ac_majflt = memory.major_faults;
+        self
+    }
+
+    pub fn with_io(mut self, io: Io) -> Self {
+        self.raw.read_char = io.read_bytes;
+        self.raw.write_char = io.write_bytes;
+        self.raw.read_syscalls = io.read_syscalls;
+        self.raw.write_syscalls = io.write_syscalls;
+        self
+    }
+
+    pub fn with_blkio(mut self, blkio: BlkIo) -> Self {
+        self.raw.read_bytes = blkio.read_bytes;
+        self.raw.write_bytes = blkio.write_bytes;
+        self.raw.cancelled_write_bytes = blkio.cancelled_write_bytes;
+        self
+    }
+
+    pub fn with_ctx_switches(mut self, ctx_switches: ContextSwitches) -> Self {
+        self.raw.nvcsw = ctx_switches.voluntary;
+        self.raw.nivcsw = ctx_switches.non_voluntary;
+        self
+    }
+
+    pub fn with_delays(mut self, delays: Delays) -> Self {
+        let nanos = |d: Duration| d.as_nanos() as u64;
+        self.raw.cpu_count = delays.cpu.count;
+        self.raw.cpu_delay_total = nanos(delays.cpu.delay_total);
+        self.raw.blkio_count = delays.blkio.count;
+        self.raw.blkio_delay_total = nanos(delays.blkio.delay_total);
+        self.raw.swapin_count = delays.swapin.count;
+        self.raw.swapin_delay_total = nanos(delays.swapin.delay_total);
+        self.raw.freepages_count = delays.freepages.count;
+        self.raw.freepages_delay_total = nanos(delays.freepages.delay_total);
+        self
+    }
+
+    /// Set any other fields of the raw struct, e.g. `hiwater_rss` or `ac_btime`.
+    pub fn with_raw<F: FnOnce(&mut taskstats)>(mut self, f: F) -> Self {
+        f(&mut self.raw);
+        self
+    }
+
+    pub fn build(&self) -> TaskStats {
+        TaskStats::from(self.raw.as_buf())
+    }
+}
+
+/// Remapped fields of `TaskStats` to be added up at once
+#[derive(Default, Clone, Copy)]
+struct Remapped {
+    cpu: Cpu,
+    memory: Memory,
+    io: Io,
+    blkio: BlkIo,
+    ctx_switches: ContextSwitches,
+    delays: Delays,
+}
+
+/// Implement `checked_*` and `saturating_*` methods along with the operators
+/// and `Sum` on a struct of counters, adding up and subtracting field by field.
+macro_rules! impl_arith {
+    ($ty:ident { $($field:ident),+ $(,)? }) => {
+        impl $ty {
+            /// Add up fields, returning `None` on overflow of any.
+            pub fn checked_add(self, rhs: Self) -> Option<Self> {
+                Some(Self {
+                    $($field: self.$field.checked_add(rhs.$field)?),+
+                })
+            }
+
+            /// Subtract fields, returning `None` if any of `rhs` is larger.
+            pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+                Some(Self {
+                    $($field: self.$field.checked_sub(rhs.$field)?),+
+                })
+            }
+
+            /// Add up fields, saturating at their maximum.
+            pub fn saturating_add(self, rhs: Self) -> Self {
+                Self {
+                    $($field: self.$field.saturating_add(rhs.$field)),+
+                }
+            }
+
+            /// Subtract fields, saturating at zero.
+            pub fn saturating_sub(self, rhs: Self) -> Self {
+                Self {
+                    $($field: self.$field.saturating_sub(rhs.$field)),+
+                }
+            }
+        }
+
+        impl_ops!($ty);
+
+        impl Sum for $ty {
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.fold(Self::default(), Add::add)
+            }
+        }
+
+        impl<'a> Sum<&'a $ty> for $ty {
+            fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+                iter.copied().sum()
+            }
+        }
+    };
+}
+
+/// Implement the operators by the saturating methods.
+macro_rules! impl_ops {
+    ($ty:ident) => {
+        impl Add for $ty {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                self.saturating_add(rhs)
+            }
+        }
+
+        impl Sub for $ty {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                self.saturating_sub(rhs)
+            }
+        }
+
+        impl AddAssign for $ty {
+            fn add_assign(&mut self, rhs: Self) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl SubAssign for $ty {
+            fn sub_assign(&mut self, rhs: Self) {
+                *self = *self - rhs;
+            }
+        }
+    };
+}
+
+impl_arith!(Cpu {
+    utime_total,
+    stime_total,
+    real_time_total,
+    virtual_time_total,
+});
+impl_arith!(Memory {
+    rss_total,
+    virt_total,
+    minor_faults,
+    major_faults,
+});
+impl_arith!(Io {
+    read_bytes,
+    write_bytes,
+    read_syscalls,
+    write_syscalls,
+});
+impl_arith!(BlkIo {
+    read_bytes,
+    write_bytes,
+    cancelled_write_bytes,
+});
+impl_arith!(ContextSwitches {
+    voluntary,
+    non_voluntary,
+});
+impl_arith!(DelayStat { count, delay_total });
+impl_arith!(Delays {
+    cpu,
+    blkio,
+    swapin,
+    freepages,
+});
+impl_arith!(Remapped {
+    cpu,
+    memory,
+    io,
+    blkio,
+    ctx_switches,
+    delays,
+});
+
+impl_ops!(TaskStats);
+
+/// Sum of stats, with identity fields of the first one, or all zeros if empty.
+impl Sum for TaskStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.reduce(Add::add).unwrap_or_default()
+    }
+}
+
+impl<'a> Sum<&'a TaskStats> for TaskStats {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
+/// Stats of all zeros, which are neutral in addition except identity fields.
+impl Default for TaskStats {
+    fn default() -> Self {
+        TaskStats::from(&[0u8; TASKSTATS_SIZE][..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_roundtrip() {
+        let mut raw: taskstats = unsafe { mem::zeroed() };
+        raw.ac_pid = 1234;
+        raw.ac_ppid = 1;
+        raw.ac_utime = 56;
+        raw.blkio_delay_total = 78;
+        let ts = TaskStats::from(raw.as_buf());
+
+        let json = serde_json::to_string(&ts).unwrap();
+        assert!(json.contains(r#""tid":1234"#));
+        assert!(json.contains(r#""utime_total":{"secs":0,"nanos":56000}"#));
+
+        let de: TaskStats = serde_json::from_str(&json).unwrap();
+        assert_eq!(ts.inner_buf[..], de.inner_buf[..]);
+        assert_eq!(1, de.inner().ac_ppid);
+        assert_eq!(ts.tid, de.tid);
+        assert_eq!(ts.cpu.utime_total, de.cpu.utime_total);
+        assert_eq!(ts.delays.blkio.delay_total, de.delays.blkio.delay_total);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_raw_size_mismatch() {
+        let ts = TaskStats::from(&[0u8; TASKSTATS_SIZE][..]);
+        let mut value = serde_json::to_value(ts).unwrap();
+        value["raw"] = serde_json::json!([1, 2, 3]);
+        let de: TaskStats = serde_json::from_value(value).unwrap();
+        assert_eq!([1, 2, 3, 0], de.inner_buf[..4]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_remapped_from_raw() {
+        let ts = TaskStats::builder()
+            .with_tid(1234)
+            .with_elapsed(Duration::from_micros(56))
+            .build();
+        let mut value = serde_json::to_value(ts).unwrap();
+        value["tid"] = serde_json::json!(5678);
+        value["cpu"]["utime_total"] = serde_json::json!({"secs": 9, "nanos": 0});
+        let de: TaskStats = serde_json::from_value(value).unwrap();
+        assert_eq!(1234, de.tid);
+        assert_eq!(Duration::ZERO, de.cpu.utime_total);
+        assert_eq!(Duration::from_micros(56), de.elapsed());
+    }
+
+    fn stats(tid: u32, utime: u64, etime: u64, hiwater_rss: u64) -> TaskStats {
+        let mut raw: taskstats = unsafe { mem::zeroed() };
+        raw.ac_pid = tid;
+        raw.ac_utime = utime;
+        raw.ac_etime = etime;
+        raw.hiwater_rss = hiwater_rss;
+        raw.cpu_count = 1;
+        raw.cpu_delay_total = utime.saturating_mul(10);
+        TaskStats::from(raw.as_buf())
+    }
+
+    #[test]
+    fn test_add_sub() {
+        let a = stats(10, 100, 1000, 50);
+        let b = stats(11, 20, 300, 80);
+
+        let sum = a + b;
+        assert_eq!(10, sum.tid);
+        assert_eq!(10, sum.inner().ac_pid);
+        assert_eq!(Duration::from_micros(120), sum.cpu.utime_total);
+        assert_eq!(120, sum.inner().ac_utime);
+        assert_eq!(Duration::from_micros(1300), sum.elapsed());
+        assert_eq!(80, sum.inner().hiwater_rss);
+        assert_eq!(2, sum.delays.cpu.count);
+        assert_eq!(Duration::from_nanos(600), sum.delays.cpu.average());
+
+        let diff = a - b;
+        assert_eq!(10, diff.tid);
+        assert_eq!(Duration::from_micros(80), diff.cpu.utime_total);
+        assert_eq!(Duration::from_micros(700), diff.elapsed());
+        assert_eq!(50, diff.inner().hiwater_rss);
+        assert_eq!(0, diff.delays.cpu.count);
+
+        let mut c = a;
+        c += b;
+        c -= b;
+        assert_eq!(a.cpu.utime_total, c.cpu.utime_total);
+        assert_eq!(a.elapsed(), c.elapsed());
+        assert_eq!(80, c.inner().hiwater_rss);
+    }
+
+    #[test]
+    fn test_add_sub_extended_delays() {
+        let fields: Vec<_> = extended_delay_fields().collect();
+        let raw = |base: u64| {
+            let mut buf = [0u8; TASKSTATS_SIZE];
+            for (i, offset) in fields.iter().enumerate() {
+                write_u64(&mut buf, *offset, base + i as u64);
+            }
+            TaskStats::from(&buf[..])
+        };
+        let a = raw(100);
+        let b = raw(10);
+
+        let sum = a + b;
+        let diff = a - b;
+        let mut tree = a;
+        tree.accumulate(&b);
+        for (i, offset) in fields.iter().enumerate() {
+            assert_eq!(110 + 2 * i as u64, read_u64(&sum.inner_buf, *offset));
+            assert_eq!(90, read_u64(&diff.inner_buf, *offset));
+            assert_eq!(110 + 2 * i as u64, read_u64(&tree.inner_buf, *offset));
+        }
+        let group: TaskStats = vec![a, b, b].into_iter().sum();
+        for (i, offset) in fields.iter().enumerate() {
+            assert_eq!(120 + 3 * i as u64, read_u64(&group.inner_buf, *offset));
+        }
+    }
+
+    #[test]
+    fn test_saturating_and_checked() {
+        let a = stats(10, 100, 1000, 0);
+        let b = stats(11, 200, 0, 0);
+        assert!((b - a).elapsed().is_zero());
+        assert!(b.checked_sub(a).is_none());
+        assert_eq!(
+            Some(Duration::from_micros(100)),
+            b.checked_sub(stats(11, 100, 0, 0))
+                .map(|d| d.cpu.utime_total)
+        );
+
+        let max = stats(10, u64::MAX, 0, 0);
+        assert_eq!(u64::MAX, (max + a).inner().ac_utime);
+        assert!(max.checked_add(a).is_none());
+        assert!(a.checked_add(b).is_some());
+
+        let io = Io {
+            read_bytes: u64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(u64::MAX, (io + io).read_bytes);
+        assert!(io.checked_add(io).is_none());
+        let delay = DelayStat {
+            count: 1,
+            delay_total: Duration::from_secs(1),
+        };
+        assert_eq!(
+            delay.delay_total,
+            (delay - delay - delay + delay).delay_total
+        );
+    }
+
+    #[test]
+    fn test_sum() {
+        let all = [
+            stats(10, 1, 10, 1),
+            stats(11, 2, 20, 3),
+            stats(12, 3, 30, 2),
+        ];
+        let sum: TaskStats = all.iter().sum();
+        assert_eq!(10, sum.tid);
+        assert_eq!(Duration::from_micros(6), sum.cpu.utime_total);
+        assert_eq!(Duration::from_micros(60), sum.elapsed());
+        assert_eq!(3, sum.inner().hiwater_rss);
+        let delays: Delays = all.iter().map(|ts| ts.delays).sum();
+        assert_eq!(3, delays.cpu.count);
+
+        let empty: TaskStats = std::iter::empty::<TaskStats>().sum();
+        assert_eq!(0, empty.tid);
+        assert!(empty.cpu.utime_total.is_zero());
+    }
+
+    #[test]
+    fn test_tgid() {
+        let ts = TaskStats::builder().with_tid(1235).with_tgid(1234).build();
+        assert_eq!(tgid_field().map(|_| 1234), ts.tgid());
+
+        let old = TaskStats::builder()
+            .with_tgid(1234)
+            .with_raw(|raw| raw.version = 11)
+            .build();
+        assert_eq!(None, old.tgid());
+        assert_eq!(None, TaskStats::builder().build().tgid());
+    }
+
+    #[test]
+    fn test_builder() {
+        let ts = TaskStats::builder()
+            .with_tid(1234)
+            .with_ppid(1)
+            .with_uid(1000)
+            .with_comm("a-very-long-command-name-over-31-bytes")
+            .with_elapsed(Duration::from_millis(5))
+            .with_cpu(Cpu {
+                utime_total: Duration::from_nanos(12_345),
+                real_time_total: Duration::from_nanos(56),
+                ..Default::default()
+            })
+            .with_memory(Memory {
+                minor_faults: 7,
+                ..Default::default()
+            })
+            .with_delays(Delays {
+                blkio: DelayStat {
+                    count: 2,
+                    delay_total: Duration::from_nanos(300),
+                },
+                ..Default::default()
+            })
+            .with_raw(|raw| raw.hiwater_rss = 42)
+            .build();
+
+        assert_eq!(1234, ts.tid);
+        assert_eq!(1234, ts.inner().ac_pid);
+        assert_eq!(1000, ts.inner().ac_uid);
+        assert_eq!("a-very-long-command-name-over-3", ts.comm());
+        assert_eq!(Duration::from_millis(5), ts.elapsed());
+        assert_eq!(Duration::from_micros(12), ts.cpu.utime_total);
+        assert_eq!(12, ts.inner().ac_utime);
+        assert_eq!(Duration::from_nanos(56), ts.cpu.real_time_total);
+        assert_eq!(7, ts.memory.minor_faults);
+        assert_eq!(7, ts.inner().ac_minflt);
+        assert_eq!(Duration::from_nanos(150), ts.delays.blkio.average());
+        assert_eq!(300, ts.inner().blkio_delay_total);
+        assert_eq!(42, ts.inner().hiwater_rss);
+        assert_eq!(c_headers::TASKSTATS_VERSION as u16, ts.inner().version);
+
+        let sum = ts + ts;
+        assert_eq!(24, sum.inner().ac_utime);
+        assert_eq!(Duration::from_micros(24), sum.cpu.utime_total);
+    }
 }
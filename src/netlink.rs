@@ -2,14 +2,18 @@ use crate::AsBuf;
 
 use log::debug;
 use netlink_sys::{self as nl, Socket, SocketAddr};
+use std::convert::TryInto;
 use std::io;
 use std::mem;
+use std::os::unix::io::AsRawFd;
 use std::process;
 use std::slice;
+use std::time::Duration;
 use thiserror::Error;
 
 const MAX_MESSAGE_SIZE: usize = 1024;
 
+/// Errors in the netlink socket/protocol layer, returned as `crate::Error::Netlink`
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum Error {
@@ -17,13 +21,15 @@ pub enum Error {
     SocketIo(#[from] io::Error),
     #[error("corrupted data read from netlink socket: {0}")]
     Protocol(String),
-    #[error("error response received from remote")]
-    ErrorResponse,
+    /// The kernel refused the request by the error number, e.g. `libc::ESRCH`
+    /// for a task which doesn't exist
+    #[error("error response received from remote: {}", io::Error::from_raw_os_error(*.0))]
+    ErrorResponse(i32),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-mod nlmsg {
+pub(crate) mod nlmsg {
     use crate::c_headers::NLMSG_ALIGNTO;
     use std::mem;
 
@@ -42,7 +48,7 @@ mod nlmsg {
     }
 }
 
-mod nla {
+pub(crate) mod nla {
     use std::mem;
 
     pub const HDRLEN: usize = align(mem::size_of::<libc::nlattr>());
@@ -66,7 +72,8 @@ mod nla {
 }
 
 /// Trait abstracting netlink socket IO.
-/// This trait is only meant to replace socket implementation at unit testing.
+/// This trait is only meant to replace socket implementation at unit testing,
+/// e.g. by `fake::FakeKernel` to test code using `Client` without privileges.
 pub trait NlSocket {
     type Addr;
 
@@ -99,11 +106,7 @@ impl Netlink<nl::Socket> {
         let mut sock = Socket::new(nl::protocols::NETLINK_GENERIC)?;
         let addr = SocketAddr::new(0, 0);
         sock.bind(&addr)?;
-        Ok(Netlink {
-            sock,
-            remote_addr: SocketAddr::new(0, 0),
-            mypid: process::id(),
-        })
+        Ok(Netlink::new(sock, SocketAddr::new(0, 0)))
     }
 
     pub fn set_rx_buf_sz<T>(&self, payload: T) -> Result<()> {
@@ -113,9 +116,39 @@ impl Netlink<nl::Socket> {
     pub fn get_rx_buf_sz(&self) -> Result<usize> {
         self.sock.get_rx_buf_sz().map_err(|err| err.into())
     }
+
+    pub fn set_rx_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        let timeout = timeout.unwrap_or_default();
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                self.sock.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &tv as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
 }
 
 impl<S: NlSocket> Netlink<S> {
+    /// Create a new `Netlink` over `sock`, sending to `remote_addr`.
+    pub fn new(sock: S, remote_addr: S::Addr) -> Netlink<S> {
+        Netlink {
+            sock,
+            remote_addr,
+            mypid: process::id(),
+        }
+    }
+
     pub fn send_cmd(
         &self,
         nlmsg_type: u16,
@@ -202,7 +235,17 @@ impl<S: NlSocket> Netlink<S> {
         }
 
         if msg.nlmsg_header.nlmsg_type == libc::NLMSG_ERROR as u16 {
-            return Err(Error::ErrorResponse);
+            // struct nlmsgerr follows the header, starting with the negated error number
+            let len = msg.nlmsg_header.nlmsg_len as usize;
+            if len < nlmsg::HDRLEN + mem::size_of::<i32>() {
+                return Err(Error::Protocol(format!("too short error message: {}", len)));
+            }
+            let error = &msg.as_buf()[nlmsg::HDRLEN..nlmsg::HDRLEN + mem::size_of::<i32>()];
+            let error = i32::from_ne_bytes(error.try_into().unwrap());
+            if error >= 0 || error == i32::MIN {
+                return Err(Error::Protocol(format!("invalid error number: {}", error)));
+            }
+            return Err(Error::ErrorResponse(-error));
         }
 
         Ok(msg)
@@ -405,6 +448,42 @@ mod tests {
         assert_eq!(PAYLOAD.as_bytes(), &resp.buf[..PAYLOAD.len()]);
     }
 
+    #[test]
+    fn test_recv_error_response() {
+        let serv_sock = nl_sock();
+        let nl = nl(&serv_sock);
+        let addr = nl.sock.local_addr().unwrap();
+
+        let recv_error = |error: i32| {
+            let len = nlmsg::HDRLEN + mem::size_of::<i32>();
+            let n = libc::nlmsghdr {
+                nlmsg_len: len as u32,
+                nlmsg_type: libc::NLMSG_ERROR as u16,
+                nlmsg_flags: 0,
+                nlmsg_seq: 0,
+                nlmsg_pid: PID,
+            };
+            let mut buf = [0u8; 64];
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    &n as *const libc::nlmsghdr as *const u8,
+                    buf.as_mut_ptr(),
+                    mem::size_of::<libc::nlmsghdr>(),
+                );
+            }
+            buf[nlmsg::HDRLEN..len].copy_from_slice(&error.to_ne_bytes());
+            serv_sock.send_to(&buf[..len], addr).unwrap();
+            nl.recv_response().err().unwrap()
+        };
+
+        assert!(matches!(
+            recv_error(-libc::ESRCH),
+            Error::ErrorResponse(libc::ESRCH)
+        ));
+        assert!(matches!(recv_error(0), Error::Protocol(_)));
+        assert!(matches!(recv_error(i32::MIN), Error::Protocol(_)));
+    }
+
     #[test]
     fn test_nlpayload() {
         struct Msg<'a>(&'a [u8]);
@@ -419,7 +498,7 @@ mod tests {
             slice::from_raw_parts(&n as *const u32 as *const u8, mem::size_of::<u32>())
         });
         assert_eq!(mem::size_of::<u32>(), m.payload_len());
-        assert_eq!(n, *m.payload_as());
+        assert_eq!(n, *m.payload_as::<u32>());
     }
 
     #[test]
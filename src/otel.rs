@@ -0,0 +1,776 @@
+use crate::container::{ContainerInfo, Record};
+use crate::TaskStats;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Default timeout of connecting and talking to a collector
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid OTLP endpoint: {0}")]
+    InvalidEndpoint(String),
+    #[error("error in communication with collector: {0}")]
+    Transport(Box<ureq::Transport>),
+    #[error("collector responded with status {0}: {1}")]
+    Status(u16, String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+type Counter = fn(&TaskStats) -> u64;
+
+/// A metric of samples with an attribute distinguishing its values.
+struct MetricDef {
+    name: &'static str,
+    unit: &'static str,
+    description: &'static str,
+    attribute: &'static str,
+    /// Values in nanoseconds to be exported as seconds in double, or else as integers
+    nanos: bool,
+    values: &'static [(&'static str, Counter)],
+}
+
+const SAMPLE_METRICS: &[MetricDef] = &[
+    MetricDef {
+        name: "process.cpu.time",
+        unit: "s",
+        description: "Total CPU seconds broken down by different CPU modes.",
+        attribute: "cpu.mode",
+        nanos: true,
+        values: &[
+            ("user", |ts| ts.cpu.utime_total.as_nanos() as u64),
+            ("system", |ts| ts.cpu.stime_total.as_nanos() as u64),
+        ],
+    },
+    MetricDef {
+        name: "process.disk.io",
+        unit: "By",
+        description: "Disk bytes transferred.",
+        attribute: "disk.io.direction",
+        nanos: false,
+        values: &[
+            ("read", |ts| ts.blkio.read_bytes),
+            ("write", |ts| ts.blkio.write_bytes),
+        ],
+    },
+    MetricDef {
+        name: "process.paging.faults",
+        unit: "{fault}",
+        description: "Number of page faults the process has made.",
+        attribute: "process.paging.fault_type",
+        nanos: false,
+        values: &[
+            ("major", |ts| ts.memory.major_faults),
+            ("minor", |ts| ts.memory.minor_faults),
+        ],
+    },
+    MetricDef {
+        name: "process.context_switches",
+        unit: "{count}",
+        description: "Number of times the process has been context switched.",
+        attribute: "process.context_switch_type",
+        nanos: false,
+        values: &[
+            ("voluntary", |ts| ts.ctx_switches.voluntary),
+            ("involuntary", |ts| ts.ctx_switches.non_voluntary),
+        ],
+    },
+    MetricDef {
+        name: "taskstats.io.bytes",
+        unit: "By",
+        description: "Bytes read and written through syscalls.",
+        attribute: "taskstats.io.direction",
+        nanos: false,
+        values: &[
+            ("read", |ts| ts.io.read_bytes),
+            ("write", |ts| ts.io.write_bytes),
+        ],
+    },
+    MetricDef {
+        name: "taskstats.delay.time",
+        unit: "s",
+        description: "Time spent waiting, by delay accounting category.",
+        attribute: "taskstats.delay.type",
+        nanos: true,
+        values: &[
+            ("cpu", |ts| ts.delays.cpu.delay_total.as_nanos() as u64),
+            ("blkio", |ts| ts.delays.blkio.delay_total.as_nanos() as u64),
+            ("swapin", |ts| {
+                ts.delays.swapin.delay_total.as_nanos() as u64
+            }),
+            ("freepages", |ts| {
+                ts.delays.freepages.delay_total.as_nanos() as u64
+            }),
+        ],
+    },
+    MetricDef {
+        name: "taskstats.delay.count",
+        unit: "{delay}",
+        description: "Count of delays, by delay accounting category.",
+        attribute: "taskstats.delay.type",
+        nanos: false,
+        values: &[
+            ("cpu", |ts| ts.delays.cpu.count),
+            ("blkio", |ts| ts.delays.blkio.count),
+            ("swapin", |ts| ts.delays.swapin.count),
+            ("freepages", |ts| ts.delays.freepages.count),
+        ],
+    },
+];
+
+struct Point {
+    attributes: Vec<(&'static str, Value)>,
+    /// `asInt` or `asDouble` with the value
+    value: (&'static str, Value),
+}
+
+struct Metric {
+    name: &'static str,
+    unit: &'static str,
+    description: &'static str,
+    points: Vec<Point>,
+}
+
+/// A task as of the previous export
+#[derive(Clone, Copy)]
+struct Task {
+    pid: u32,
+    stats: TaskStats,
+}
+
+impl Task {
+    /// Tell whether `stats` are of this task rather than of another one
+    /// reusing its ID. `ac_btime` is in seconds and computed backwards from
+    /// the current time by the kernel, so it can differ by one between queries.
+    fn is(&self, pid: u32, stats: &TaskStats) -> bool {
+        let (a, b) = (self.stats.inner().ac_btime, stats.inner().ac_btime);
+        self.pid == pid && a.max(b) - a.min(b) <= 1
+    }
+}
+
+/// Increase of counters of a process since the previous export
+struct Increase {
+    comm: String,
+    container: Option<ContainerInfo>,
+    stats: TaskStats,
+}
+
+/// Exports metrics of tasks to an OpenTelemetry collector over OTLP/HTTP.
+///
+/// Metrics are encoded in the JSON encoding of OTLP and posted to an `http://`
+/// endpoint, typically a collector or an agent running on the same host.
+/// Names follow the semantic conventions of process metrics where they exist,
+/// and the others are under the `taskstats.` namespace:
+///
+/// | Metric                     | Unit    | Attribute                     |
+/// |----------------------------|---------|-------------------------------|
+/// | `process.cpu.time`         | s       | `cpu.mode`                    |
+/// | `process.disk.io`          | By      | `disk.io.direction`           |
+/// | `process.paging.faults`    | {fault} | `process.paging.fault_type`   |
+/// | `process.context_switches` | {count} | `process.context_switch_type` |
+/// | `taskstats.io.bytes`       | By      | `taskstats.io.direction`      |
+/// | `taskstats.delay.time`     | s       | `taskstats.delay.type`        |
+/// | `taskstats.delay.count`    | {delay} | `taskstats.delay.type`        |
+/// | `taskstats.task.exits`     | {task}  |                               |
+/// | `taskstats.task.lifetime`  | s       |                               |
+///
+/// All metrics are monotonic sums with delta temporality.
+///
+/// Counters of tasks are exported per process as the increase since the
+/// previous export, with attributes `process.pid` and
+/// `process.executable.name`. The increase of a task which exited is taken
+/// out of its exit record, so that it is not lost along with the task. Tasks
+/// started before the previous export and not seen by it, e.g. all tasks at
+/// the first export, are only remembered to take differences of the next one.
+/// Exit records of tasks not seen by the previous export are counted only if
+/// they tell their processes by `TaskStats::tgid`.
+///
+/// Exit records are also counted per command name into
+/// `taskstats.task.exits` and `taskstats.task.lifetime`.
+///
+/// Points of records enriched with their containers also have attributes
+/// `container.id` and `k8s.pod.uid` if resolved.
+pub struct OtlpExporter {
+    agent: ureq::Agent,
+    url: String,
+    resource: Vec<(String, String)>,
+    /// Live tasks as of the previous export by TID
+    tasks: HashMap<u32, Task>,
+    /// Tasks gone by the previous export without their exit records, which
+    /// are waited for until the next one
+    missing: HashMap<u32, Task>,
+    last_export: SystemTime,
+}
+
+impl OtlpExporter {
+    /// Create a new `OtlpExporter` posting to `endpoint` like `http://localhost:4318`.
+    ///
+    /// The path defaults to `/v1/metrics` when the endpoint has none. The
+    /// resource has `service.name` of `taskstats` and `host.name` of this host.
+    pub fn new(endpoint: &str) -> Result<Self> {
+        let rest = endpoint
+            .strip_prefix("http://")
+            .ok_or_else(|| Error::InvalidEndpoint(endpoint.to_string()))?;
+        let (host, path) = match rest.find('/') {
+            Some(i) if i + 1 < rest.len() => (&rest[..i], &rest[i..]),
+            Some(i) => (&rest[..i], "/v1/metrics"),
+            None => (rest, "/v1/metrics"),
+        };
+        if host.is_empty() {
+            return Err(Error::InvalidEndpoint(endpoint.to_string()));
+        }
+        let mut resource = vec![("service.name".to_string(), "taskstats".to_string())];
+        if let Some(hostname) = hostname() {
+            resource.push(("host.name".to_string(), hostname));
+        }
+        Ok(Self {
+            agent: agent(DEFAULT_TIMEOUT),
+            url: format!("http://{}{}", host, path),
+            resource,
+            tasks: HashMap::new(),
+            missing: HashMap::new(),
+            last_export: SystemTime::now(),
+        })
+    }
+
+    /// Add or replace an attribute of the resource.
+    pub fn with_resource_attribute(mut self, key: &str, value: &str) -> Self {
+        self.resource.retain(|(k, _)| k != key);
+        self.resource.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set the timeout of connecting and talking to the collector. Defaults to 10 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.agent = agent(timeout);
+        self
+    }
+
+    /// Export the increase of counters since the previous export out of stats
+    /// of live tasks along with the IDs of their processes and exit records
+    /// received from `Client::listen_registered` since then.
+    ///
+    /// Per-tgid records are ignored. When the export fails, nothing is taken
+    /// as exported, so that the next export covers the increase since the
+    /// last successful one given the exit records again.
+    pub fn export<R: Record>(&mut self, samples: &[(u32, R)], exits: &[R]) -> Result<()> {
+        let exits: Vec<_> = exits.iter().filter(|r| r.stats().tid != 0).collect();
+        let exited: HashSet<u32> = exits.iter().map(|r| r.stats().tid).collect();
+        let mut exit_of: HashMap<u32, &R> = exits.iter().map(|r| (r.stats().tid, *r)).collect();
+        let since = self
+            .last_export
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let previous = |tid: u32, pid: u32, ts: &TaskStats| {
+            self.previous_task(tid)
+                .filter(|task| task.is(pid, ts))
+                .map(|task| task.stats)
+                .or_else(|| {
+                    // Started after the previous export, so everything is the increase
+                    let btime = u64::from(ts.inner().ac_btime);
+                    (btime + 1 >= since.as_secs()).then(TaskStats::default)
+                })
+        };
+
+        let mut tasks = HashMap::new();
+        let mut increases: BTreeMap<u32, Increase> = BTreeMap::new();
+        let mut increase = |pid: u32, record: &R, stats: TaskStats| {
+            let increase = increases.entry(pid).or_insert_with(|| Increase {
+                comm: record.stats().comm(),
+                container: record.container().cloned(),
+                stats: TaskStats::default(),
+            });
+            increase.stats += stats;
+        };
+        for (pid, record) in samples {
+            let ts = record.stats();
+            let last = match exit_of.remove(&ts.tid) {
+                Some(exit) => exit.stats(),
+                None => {
+                    tasks.insert(
+                        ts.tid,
+                        Task {
+                            pid: *pid,
+                            stats: *ts,
+                        },
+                    );
+                    ts
+                }
+            };
+            if let Some(prev) = previous(ts.tid, *pid, ts) {
+                increase(*pid, record, *last - prev);
+            }
+        }
+        for exit in exits
+            .iter()
+            .filter(|r| exit_of.contains_key(&r.stats().tid))
+        {
+            let ts = exit.stats();
+            let pid = self
+                .previous_task(ts.tid)
+                .filter(|task| task.is(task.pid, ts))
+                .map(|task| task.pid)
+                .or_else(|| ts.tgid());
+            let pid = match pid {
+                Some(pid) => pid,
+                None => continue,
+            };
+            if let Some(prev) = previous(ts.tid, pid, ts) {
+                increase(pid, exit, *ts - prev);
+            }
+        }
+        let missing: HashMap<_, _> = self
+            .tasks
+            .iter()
+            .filter(|(tid, _)| !tasks.contains_key(tid) && !exited.contains(tid))
+            .map(|(tid, task)| (*tid, *task))
+            .collect();
+
+        let mut metrics = sample_metrics(&increases);
+        metrics.extend(exit_metrics(&exits));
+        let now = SystemTime::now();
+        if metrics.iter().any(|m| !m.points.is_empty()) {
+            let body = encode(&self.resource, &metrics, self.last_export, now);
+            self.post(&body)?;
+        }
+        self.tasks = tasks;
+        self.missing = missing;
+        self.last_export = now;
+        Ok(())
+    }
+
+    /// Return the task of `tid` as of the previous export, live or missing.
+    fn previous_task(&self, tid: u32) -> Option<&Task> {
+        self.tasks.get(&tid).or_else(|| self.missing.get(&tid))
+    }
+
+    fn post(&self, body: &[u8]) -> Result<()> {
+        match self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_bytes(body)
+        {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                Err(Error::Status(status, body.trim().to_string()))
+            }
+            Err(ureq::Error::Transport(e)) => Err(Error::Transport(Box::new(e))),
+        }
+    }
+}
+
+fn agent(timeout: Duration) -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(timeout).build()
+}
+
+fn hostname() -> Option<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return None;
+    }
+    buf[buf.len() - 1] = 0;
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+fn unix_nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Metrics of the increase of counters of processes
+fn sample_metrics(increases: &BTreeMap<u32, Increase>) -> Vec<Metric> {
+    SAMPLE_METRICS
+        .iter()
+        .map(|def| {
+            let mut points = Vec::new();
+            for (pid, increase) in increases {
+                for (attribute, counter) in def.values {
+                    let delta = counter(&increase.stats);
+                    let mut attributes = vec![
+                        ("process.pid", int(u64::from(*pid))),
+                        ("process.executable.name", string(&increase.comm)),
+                        (def.attribute, string(attribute)),
+                    ];
+                    if let Some(container) = &increase.container {
+                        attributes.extend(container_attributes(container));
+                    }
+                    points.push(Point {
+                        attributes,
+                        value: if def.nanos {
+                            ("asDouble", json!(delta as f64 / 1e9))
+                        } else {
+                            ("asInt", json!(delta.to_string()))
+                        },
+                    });
+                }
+            }
+            Metric {
+                name: def.name,
+                unit: def.unit,
+                description: def.description,
+                points,
+            }
+        })
+        .collect()
+}
+
+/// Metrics of exit records counted per command name and container
+fn exit_metrics<R: Record>(exits: &[&R]) -> Vec<Metric> {
+    type Key = (String, Option<String>, Option<String>);
+    let mut by_comm: BTreeMap<Key, (u64, Duration)> = BTreeMap::new();
+    for record in exits {
+        let ts = record.stats();
+        let container = record.container().cloned().unwrap_or_default();
+        let key = (ts.comm(), container.container_id, container.pod_uid);
+        let (count, lifetime) = by_comm.entry(key).or_default();
+        *count += 1;
+        *lifetime += ts.elapsed();
+    }
+    let mut count = Metric {
+        name: "taskstats.task.exits",
+        unit: "{task}",
+        description: "Number of exited tasks.",
+        points: Vec::new(),
+    };
+    let mut lifetime = Metric {
+        name: "taskstats.task.lifetime",
+        unit: "s",
+        description: "Total lifetime of exited tasks.",
+        points: Vec::new(),
+    };
+    for ((comm, container_id, pod_uid), (n, elapsed)) in by_comm {
+        let container = ContainerInfo {
+            container_id,
+            pod_uid,
+            ..Default::default()
+        };
+        let attributes = || {
+            let mut attributes = vec![("process.executable.name", string(&comm))];
+            attributes.extend(container_attributes(&container));
+            attributes
+        };
+        count.points.push(Point {
+            attributes: attributes(),
+            value: ("asInt", json!(n.to_string())),
+        });
+        lifetime.points.push(Point {
+            attributes: attributes(),
+            value: ("asDouble", json!(elapsed.as_secs_f64())),
+        });
+    }
+    vec![count, lifetime]
+}
+
+/// Attributes of the container a task runs in, which are left out if unresolved
+fn container_attributes(container: &ContainerInfo) -> Vec<(&'static str, Value)> {
+    let mut attributes = Vec::new();
+    if let Some(id) = &container.container_id {
+        attributes.push(("container.id", string(id)));
+    }
+    if let Some(uid) = &container.pod_uid {
+        attributes.push(("k8s.pod.uid", string(uid)));
+    }
+    attributes
+}
+
+fn string(value: &str) -> Value {
+    json!({ "stringValue": value })
+}
+
+/// 64-bit integers are encoded into strings in the JSON encoding of OTLP
+fn int(value: u64) -> Value {
+    json!({ "intValue": value.to_string() })
+}
+
+fn attribute(key: &str, value: &Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+/// Encode metrics into an `ExportMetricsServiceRequest` in the JSON encoding of OTLP
+fn encode(
+    resource: &[(String, String)],
+    metrics: &[Metric],
+    start: SystemTime,
+    end: SystemTime,
+) -> Vec<u8> {
+    let (start, end) = (unix_nanos(start), unix_nanos(end));
+    let metrics: Vec<_> = metrics
+        .iter()
+        .filter(|m| !m.points.is_empty())
+        .map(|metric| {
+            let points: Vec<_> = metric
+                .points
+                .iter()
+                .map(|point| {
+                    let (kind, value) = &point.value;
+                    json!({
+                        "attributes": point
+                            .attributes
+                            .iter()
+                            .map(|(key, value)| attribute(key, value))
+                            .collect::<Vec<_>>(),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": end,
+                        *kind: value,
+                    })
+                })
+                .collect();
+            json!({
+                "name": metric.name,
+                "unit": metric.unit,
+                "description": metric.description,
+                "sum": {
+                    "aggregationTemporality": 1,
+                    "isMonotonic": true,
+                    "dataPoints": points,
+                },
+            })
+        })
+        .collect();
+    let resource: Vec<_> = resource
+        .iter()
+        .map(|(key, value)| attribute(key, &string(value)))
+        .collect();
+    let request = json!({
+        "resourceMetrics": [{
+            "resource": { "attributes": resource },
+            "scopeMetrics": [{
+                "scope": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "metrics": metrics,
+            }],
+        }],
+    });
+    request.to_string().into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{taskstats, AsBuf};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::mem;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn stats(tid: u32, utime: u64, blkio_read: u64) -> TaskStats {
+        let mut raw: taskstats = unsafe { mem::zeroed() };
+        raw.ac_pid = tid;
+        raw.ac_etime = 3_000_000;
+        raw.ac_utime = utime;
+        raw.read_bytes = blkio_read;
+        raw.ac_comm[..3].copy_from_slice(&[b'f' as _, b'o' as _, b'o' as _]);
+        TaskStats::from(raw.as_buf())
+    }
+
+    /// Stats of a task started just now
+    fn new_stats(tid: u32, utime: u64) -> TaskStats {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        TaskStats::builder()
+            .with_raw(|raw| {
+                raw.ac_pid = tid;
+                raw.ac_utime = utime;
+                raw.ac_btime = now.as_secs() as u32;
+            })
+            .build()
+    }
+
+    /// Run a mock collector responding `statuses` in order and the last one
+    /// after that, which passes request paths and bodies to the receiver
+    fn collector(statuses: &'static [&'static str]) -> (String, mpsc::Receiver<(String, Value)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim_end().is_empty() {
+                        break;
+                    }
+                    let (name, value) = header.split_once(':').unwrap();
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let path = request_line.split_whitespace().nth(1).unwrap().to_string();
+                tx.send((path, serde_json::from_slice(&body).unwrap()))
+                    .unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}",
+                    statuses[i.min(statuses.len() - 1)]
+                )
+                .unwrap();
+            }
+        });
+        (endpoint, rx)
+    }
+
+    fn metric<'a>(request: &'a Value, name: &str) -> &'a Value {
+        request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|m| m["name"] == name)
+            .unwrap()
+    }
+
+    fn cpu_user(request: &Value) -> &Value {
+        &metric(request, "process.cpu.time")["sum"]["dataPoints"][0]["asDouble"]
+    }
+
+    #[test]
+    fn test_export_samples() {
+        let (endpoint, rx) = collector(&["200 OK"]);
+        let mut exporter = OtlpExporter::new(&endpoint).unwrap();
+        exporter
+            .export(&[(10, stats(10, 1_000_000, 0)), (10, stats(11, 0, 0))], &[])
+            .unwrap();
+        exporter
+            .export(
+                &[
+                    (10, stats(10, 1_500_000, 4096)),
+                    (10, stats(11, 1_000_000, 0)),
+                ],
+                &[],
+            )
+            .unwrap();
+
+        let (path, request) = rx.recv().unwrap();
+        assert_eq!("/v1/metrics", path);
+        let resource = &request["resourceMetrics"][0]["resource"]["attributes"][0];
+        assert_eq!("service.name", resource["key"]);
+        assert_eq!("taskstats", resource["value"]["stringValue"]);
+
+        let cpu = metric(&request, "process.cpu.time");
+        assert_eq!("s", cpu["unit"]);
+        assert_eq!(1, cpu["sum"]["aggregationTemporality"]);
+        let user = &cpu["sum"]["dataPoints"][0];
+        assert_eq!("10", user["attributes"][0]["value"]["intValue"]);
+        assert_eq!("foo", user["attributes"][1]["value"]["stringValue"]);
+        assert_eq!("user", user["attributes"][2]["value"]["stringValue"]);
+        assert_eq!(1.5, user["asDouble"]);
+
+        let disk = metric(&request, "process.disk.io");
+        assert_eq!("4096", disk["sum"]["dataPoints"][0]["asInt"]);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_export_exited_and_new_tasks() {
+        let (endpoint, rx) = collector(&["200 OK"]);
+        let mut exporter = OtlpExporter::new(&endpoint).unwrap();
+        exporter
+            .export(
+                &[
+                    (10, stats(10, 1_000_000, 0)),
+                    (10, stats(11, 1_000_000, 0)),
+                    (20, stats(20, 1_000_000, 0)),
+                ],
+                &[],
+            )
+            .unwrap();
+
+        // Thread 11 exits, thread 12 starts and the ID 20 is reused by a new process
+        exporter
+            .export(
+                &[
+                    (10, stats(10, 1_500_000, 0)),
+                    (10, new_stats(12, 500_000)),
+                    (20, new_stats(20, 250_000)),
+                ],
+                &[stats(11, 2_000_000, 0)],
+            )
+            .unwrap();
+        let (_, request) = rx.recv().unwrap();
+        let points = &metric(&request, "process.cpu.time")["sum"]["dataPoints"];
+        assert_eq!("10", points[0]["attributes"][0]["value"]["intValue"]);
+        assert_eq!(2.0, points[0]["asDouble"]);
+        assert_eq!("20", points[2]["attributes"][0]["value"]["intValue"]);
+        assert_eq!(0.25, points[2]["asDouble"]);
+        let exits = metric(&request, "taskstats.task.exits");
+        assert_eq!("1", exits["sum"]["dataPoints"][0]["asInt"]);
+
+        // Thread 12 gone before its exit record arrives
+        exporter
+            .export(&[(10, stats(10, 1_500_000, 0))], &[])
+            .unwrap();
+        let (_, request) = rx.recv().unwrap();
+        assert_eq!(0.0, *cpu_user(&request));
+        exporter
+            .export(&[(10, stats(10, 1_500_000, 0))], &[new_stats(12, 750_000)])
+            .unwrap();
+        let (_, request) = rx.recv().unwrap();
+        assert_eq!(0.25, *cpu_user(&request));
+    }
+
+    #[test]
+    fn test_export_exits() {
+        let (endpoint, rx) = collector(&["200 OK"]);
+        let mut exporter = OtlpExporter::new(&format!("{}/custom/path", endpoint)).unwrap();
+        exporter
+            .export(&[], &[stats(10, 0, 0), stats(11, 0, 0), stats(0, 0, 0)])
+            .unwrap();
+
+        let (path, request) = rx.recv().unwrap();
+        assert_eq!("/custom/path", path);
+        let exits = metric(&request, "taskstats.task.exits");
+        assert_eq!("2", exits["sum"]["dataPoints"][0]["asInt"]);
+        let lifetime = metric(&request, "taskstats.task.lifetime");
+        assert_eq!(6.0, lifetime["sum"]["dataPoints"][0]["asDouble"]);
+    }
+
+    #[test]
+    fn test_export_error() {
+        let (endpoint, rx) = collector(&["400 Bad Request", "200 OK"]);
+        let mut exporter = OtlpExporter::new(&endpoint).unwrap();
+        exporter
+            .export(&[(10, stats(10, 1_000_000, 0))], &[])
+            .unwrap();
+        match exporter.export(&[(10, stats(10, 2_000_000, 0))], &[stats(11, 0, 0)]) {
+            Err(Error::Status(400, body)) => assert_eq!("{}", body),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // The next export covers the increase since the first one
+        exporter
+            .export(&[(10, stats(10, 3_000_000, 0))], &[stats(11, 0, 0)])
+            .unwrap();
+        let (_, failed) = rx.recv().unwrap();
+        let (_, request) = rx.recv().unwrap();
+        assert_eq!(
+            metric(&failed, "process.cpu.time")["sum"]["dataPoints"][0]["startTimeUnixNano"],
+            metric(&request, "process.cpu.time")["sum"]["dataPoints"][0]["startTimeUnixNano"]
+        );
+        assert_eq!(2.0, *cpu_user(&request));
+        let exits = metric(&request, "taskstats.task.exits");
+        assert_eq!("1", exits["sum"]["dataPoints"][0]["asInt"]);
+    }
+
+    #[test]
+    fn test_invalid_endpoint() {
+        assert!(OtlpExporter::new("https://localhost:4318").is_err());
+        assert!(OtlpExporter::new("http://").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Trait abstracting reads of the proc filesystem, to read the one mounted
+/// somewhere else than /proc, e.g. the host's one mounted in a container, or
+/// fixtures at unit testing.
+///
+/// Only the primitives have to be implemented; the provided methods parse
+/// files read through them and return `None` once a task has gone.
+pub trait ProcFs: Send + Sync {
+    /// Read the file `name` of the task `tid`, e.g. `status` for /proc/<tid>/status.
+    fn read_task_file(&self, tid: u32, name: &str) -> io::Result<Vec<u8>>;
+
+    /// Read the symbolic link `name` of the task `tid`, e.g. `ns/pid`.
+    fn read_task_link(&self, tid: u32, name: &str) -> io::Result<PathBuf>;
+
+    /// List IDs of all processes.
+    fn pids(&self) -> io::Result<Vec<u32>>;
+
+    /// List IDs of all threads of the process `pid`.
+    fn tids(&self, pid: u32) -> io::Result<Vec<u32>>;
+
+    /// Return the command line of a task with arguments joined by spaces,
+    /// which is empty for kernel threads and zombies.
+    fn cmdline(&self, tid: u32) -> Option<String> {
+        let raw = self.read_task_file(tid, "cmdline").ok()?;
+        let args: Vec<_> = raw
+            .split(|&b| b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(String::from_utf8_lossy)
+            .collect();
+        if args.is_empty() {
+            return None;
+        }
+        Some(args.join(" "))
+    }
+
+    /// Return the ID of the process which a task belongs to.
+    fn tgid(&self, tid: u32) -> Option<u32> {
+        let status = self.read_task_file(tid, "status").ok()?;
+        parse_status_id(&String::from_utf8_lossy(&status), "Tgid:")
+    }
+
+    /// Return the ID of the parent process of a task, 0 for init and kthreadd.
+    fn ppid(&self, tid: u32) -> Option<u32> {
+        let status = self.read_task_file(tid, "status").ok()?;
+        parse_status_id(&String::from_utf8_lossy(&status), "PPid:")
+    }
+
+    /// Return the ID of a task in its innermost PID namespace.
+    fn ns_pid(&self, tid: u32) -> Option<u32> {
+        let status = self.read_task_file(tid, "status").ok()?;
+        parse_ns_pid(&String::from_utf8_lossy(&status))
+    }
+
+    /// Return the inode number of the PID namespace of a task.
+    fn pid_ns(&self, tid: u32) -> Option<u64> {
+        let link = self.read_task_link(tid, "ns/pid").ok()?;
+        parse_ns_inode(link.to_str()?)
+    }
+
+    /// Return the path of the cgroup of a task, see `parse_cgroup`.
+    fn cgroup(&self, tid: u32) -> Option<String> {
+        let content = self.read_task_file(tid, "cgroup").ok()?;
+        parse_cgroup(&String::from_utf8_lossy(&content))
+    }
+}
+
+/// The proc filesystem mounted at a directory, /proc by default.
+#[derive(Debug, Clone)]
+pub struct ProcDir {
+    root: PathBuf,
+}
+
+impl Default for ProcDir {
+    fn default() -> Self {
+        Self::new("/proc")
+    }
+}
+
+impl ProcDir {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn list_ids(&self, dir: PathBuf) -> io::Result<Vec<u32>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            if let Some(id) = entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+}
+
+impl ProcFs for ProcDir {
+    fn read_task_file(&self, tid: u32, name: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.root.join(tid.to_string()).join(name))
+    }
+
+    fn read_task_link(&self, tid: u32, name: &str) -> io::Result<PathBuf> {
+        fs::read_link(self.root.join(tid.to_string()).join(name))
+    }
+
+    fn pids(&self) -> io::Result<Vec<u32>> {
+        self.list_ids(self.root.clone())
+    }
+
+    fn tids(&self, pid: u32) -> io::Result<Vec<u32>> {
+        self.list_ids(self.root.join(pid.to_string()).join("task"))
+    }
+}
+
+/// Return the proc filesystem at /proc, read by default.
+pub(crate) fn system() -> Arc<dyn ProcFs> {
+    Arc::new(ProcDir::default())
+}
+
+/// Take the ID of `field`, e.g. `Tgid:`, out of /proc/<pid>/status
+fn parse_status_id(status: &str, field: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(field))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// Take the ID in the innermost PID namespace out of `NSpid` of /proc/<pid>/status
+fn parse_ns_pid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("NSpid:"))?
+        .split_whitespace()
+        .last()?
+        .parse()
+        .ok()
+}
+
+/// Take the inode number out of a namespace link like `pid:[4026531836]`
+fn parse_ns_inode(link: &str) -> Option<u64> {
+    link.strip_prefix("pid:[")?.strip_suffix(']')?.parse().ok()
+}
+
+/// Take the cgroup path out of /proc/<pid>/cgroup, the one in the cgroup v2
+/// hierarchy if any, otherwise of the first v1 hierarchy listed
+fn parse_cgroup(content: &str) -> Option<String> {
+    let mut first = None;
+    for line in content.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (hierarchy, _, path) = (fields.next()?, fields.next()?, fields.next()?);
+        if hierarchy == "0" {
+            return Some(path.to_string());
+        }
+        first.get_or_insert(path);
+    }
+    first.map(|p| p.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn fixture() -> ProcDir {
+        ProcDir::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/proc"))
+    }
+
+    #[test]
+    fn test_proc_dir() {
+        let proc = fixture();
+        assert_eq!(vec![1, 1234, 2345, 3456], proc.pids().unwrap());
+        assert_eq!(vec![1234, 1235], proc.tids(1234).unwrap());
+        assert!(proc.tids(1).is_err());
+
+        assert_eq!(
+            Some("nginx: master process -g daemon off;".to_string()),
+            proc.cmdline(1234)
+        );
+        assert_eq!(None, proc.cmdline(1));
+        assert_eq!(Some(1234), proc.tgid(1234));
+        assert_eq!(Some(1200), proc.ppid(1234));
+        assert_eq!(Some(0), proc.ppid(1));
+        assert_eq!(Some(1), proc.ns_pid(1234));
+        assert_eq!(Some(4026532448), proc.pid_ns(1234));
+        assert_eq!(Some("/init.scope".to_string()), proc.cgroup(1));
+        assert_eq!(None, proc.cgroup(9999));
+    }
+
+    #[test]
+    fn test_parse_status_id() {
+        let status = "Name:\tfoo\nTgid:\t42\nPid:\t43\nPPid:\t1\n";
+        assert_eq!(Some(42), parse_status_id(status, "Tgid:"));
+        assert_eq!(Some(1), parse_status_id(status, "PPid:"));
+        assert_eq!(None, parse_status_id("Name:\tfoo\n", "Tgid:"));
+    }
+
+    #[test]
+    fn test_parse_cgroup() {
+        assert_eq!(
+            Some("/system.slice/nginx.service".to_string()),
+            parse_cgroup("0::/system.slice/nginx.service\n")
+        );
+        assert_eq!(
+            Some("/user.slice".to_string()),
+            parse_cgroup("12:cpu,cpuacct:/user.slice\n1:name=systemd:/user.slice/x\n")
+        );
+        assert_eq!(None, parse_cgroup(""));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::journal::{read_record, stats_from_raw, write_record};
+use crate::{TaskStats, TASKSTATS_SIZE};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Magic bytes at the head of a recording, which also tell the format version
+const MAGIC: &[u8; 8] = b"TSRECRD1";
+
+/// Size of the header of a snapshot: the timestamp and the size of raw structs
+const SNAPSHOT_HEADER_SIZE: usize = 12;
+
+/// Snapshots larger than this are considered corrupted
+const MAX_SNAPSHOT_SIZE: usize = 1 << 30;
+
+/// Stats of all tasks sampled at once.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Time when the tasks were sampled
+    pub time: SystemTime,
+    /// Stats of tasks along with the IDs of their processes
+    pub samples: Vec<(u32, TaskStats)>,
+}
+
+/// Writes snapshots of tasks to a file, to be replayed by `RecordingReader`
+/// e.g. on another host without privileges.
+///
+/// A recording starts with 8 magic bytes followed by snapshots framed in the
+/// same way as records of `journal::JournalWriter`, with a payload of:
+/// * the time of sampling in nanoseconds since the UNIX epoch, u64 in little endian
+/// * the size of the raw `taskstats` struct, u32 in little endian
+/// * a sample per task: the process ID, u32 in little endian, followed by
+///   the raw `taskstats` struct
+///
+/// Every snapshot is written out to the file as a whole, so that a recording
+/// interrupted at any time can be replayed up to the last complete snapshot.
+pub struct RecordingWriter {
+    file: BufWriter<File>,
+}
+
+impl RecordingWriter {
+    /// Create a recording at `path`, truncating the file if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        Ok(Self { file })
+    }
+
+    /// Write a snapshot of `samples`, pairs of process ID and stats of a task,
+    /// taken at `time`.
+    pub fn write(&mut self, time: SystemTime, samples: &[(u32, TaskStats)]) -> io::Result<()> {
+        let nanos = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut payload =
+            Vec::with_capacity(SNAPSHOT_HEADER_SIZE + samples.len() * (4 + TASKSTATS_SIZE));
+        payload.extend_from_slice(&nanos.to_le_bytes());
+        payload.extend_from_slice(&(TASKSTATS_SIZE as u32).to_le_bytes());
+        for (pid, ts) in samples {
+            payload.extend_from_slice(&pid.to_le_bytes());
+            payload.extend_from_slice(&ts.inner_buf);
+        }
+        write_record(&mut self.file, &payload)?;
+        self.file.flush()
+    }
+}
+
+/// Reads snapshots out of a recording written by `RecordingWriter`.
+///
+/// Reading stops at a snapshot partially written or corrupted.
+pub struct RecordingReader {
+    reader: BufReader<File>,
+}
+
+impl RecordingReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("not a recording: {}", path.display()),
+            ));
+        }
+        Ok(Self { reader })
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = io::Result<Snapshot>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match read_record(&mut self.reader, MAX_SNAPSHOT_SIZE) {
+            Ok(payload) => decode(&payload?).map(Ok),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Decode a snapshot, or None if it's inconsistent in spite of its CRC
+fn decode(payload: &[u8]) -> Option<Snapshot> {
+    if payload.len() < SNAPSHOT_HEADER_SIZE {
+        return None;
+    }
+    let nanos = u64::from_le_bytes(payload[..8].try_into().unwrap());
+    let raw_size = u32::from_le_bytes(payload[8..12].try_into().unwrap()) as usize;
+    let samples = &payload[SNAPSHOT_HEADER_SIZE..];
+    let sample_size = 4 + raw_size;
+    if raw_size == 0 || samples.len() % sample_size != 0 {
+        return None;
+    }
+    let samples = samples
+        .chunks(sample_size)
+        .map(|sample| {
+            let pid = u32::from_le_bytes(sample[..4].try_into().unwrap());
+            (pid, stats_from_raw(&sample[4..]))
+        })
+        .collect();
+    Some(Snapshot {
+        time: UNIX_EPOCH + Duration::from_nanos(nanos),
+        samples,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cpu;
+    use std::fs::{self, OpenOptions};
+    use std::path::PathBuf;
+
+    fn stats(tid: u32, utime: u64) -> TaskStats {
+        TaskStats::builder()
+            .with_tid(tid)
+            .with_cpu(Cpu {
+                utime_total: Duration::from_micros(utime),
+                ..Default::default()
+            })
+            .build()
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "linux-taskstats-recording-{}-{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    #[test]
+    fn test_write_read() {
+        let path = temp_file("write-read");
+        let time = UNIX_EPOCH + Duration::from_secs(1_680_000_000);
+        let mut writer = RecordingWriter::create(&path).unwrap();
+        writer
+            .write(time, &[(10, stats(10, 100)), (10, stats(11, 200))])
+            .unwrap();
+        writer.write(time + Duration::from_secs(5), &[]).unwrap();
+        writer
+            .write(time + Duration::from_secs(10), &[(10, stats(10, 300))])
+            .unwrap();
+        drop(writer);
+
+        let snapshots: Vec<_> = RecordingReader::open(&path)
+            .unwrap()
+            .map(|s| s.unwrap())
+            .collect();
+        assert_eq!(3, snapshots.len());
+        assert_eq!(time, snapshots[0].time);
+        assert_eq!(2, snapshots[0].samples.len());
+        assert_eq!(
+            (10, 11),
+            (snapshots[0].samples[1].0, snapshots[0].samples[1].1.tid)
+        );
+        assert!(snapshots[1].samples.is_empty());
+        assert_eq!(
+            Duration::from_micros(300),
+            snapshots[2].samples[0].1.cpu.utime_total
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_partial_tail() {
+        let path = temp_file("partial-tail");
+        let mut writer = RecordingWriter::create(&path).unwrap();
+        writer
+            .write(SystemTime::now(), &[(1, stats(1, 1))])
+            .unwrap();
+        drop(writer);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 1, 0, 0, 1, 2, 3, 4, 5]).unwrap();
+        drop(file);
+
+        let snapshots: Vec<_> = RecordingReader::open(&path).unwrap().collect();
+        assert_eq!(1, snapshots.len());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_large_snapshot() {
+        let path = temp_file("truncated-large");
+        let mut writer = RecordingWriter::create(&path).unwrap();
+        writer
+            .write(SystemTime::now(), &[(1, stats(1, 1))])
+            .unwrap();
+        drop(writer);
+        // A header claiming a snapshot of nearly 1 GiB followed by a few bytes
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&((MAX_SNAPSHOT_SIZE - 1) as u32).to_le_bytes())
+            .unwrap();
+        file.write_all(&[0; 4 + 16]).unwrap();
+        drop(file);
+
+        let snapshots: Vec<_> = RecordingReader::open(&path).unwrap().collect();
+        assert_eq!(1, snapshots.len());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_not_a_recording() {
+        let path = temp_file("not-a-recording");
+        fs::write(&path, b"TSJOURN1").unwrap();
+        assert!(RecordingReader::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
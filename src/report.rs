@@ -0,0 +1,66 @@
+use crate::TaskStats;
+use std::time::Duration;
+
+/// A bucket of a histogram of task lifetimes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bucket {
+    /// Inclusive lower bound
+    pub min: Duration,
+    /// Exclusive upper bound
+    pub max: Duration,
+    pub count: usize,
+}
+
+/// Make a histogram of lifetimes of tasks in buckets of powers of 2 in
+/// microseconds, from the shortest to the longest one.
+pub fn lifetime_histogram(records: &[TaskStats]) -> Vec<Bucket> {
+    let mut counts = [0usize; 64];
+    for ts in records {
+        let us = ts.inner().ac_etime;
+        // [0, 2) falls in the first bucket, [2^n, 2^(n+1)) in the n-th
+        let n = (63 - us.max(1).leading_zeros()) as usize;
+        counts[n] += 1;
+    }
+    let first = match counts.iter().position(|c| *c > 0) {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    let last = counts.iter().rposition(|c| *c > 0).unwrap();
+    (first..=last)
+        .map(|n| Bucket {
+            min: Duration::from_micros(if n == 0 { 0 } else { 1 << n }),
+            max: Duration::from_micros(1u64.checked_shl(n as u32 + 1).unwrap_or(u64::MAX)),
+            count: counts[n],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(tid: u32, etime: u64) -> TaskStats {
+        TaskStats::builder()
+            .with_tid(tid)
+            .with_elapsed(Duration::from_micros(etime))
+            .build()
+    }
+
+    #[test]
+    fn test_lifetime_histogram() {
+        assert!(lifetime_histogram(&[]).is_empty());
+
+        let records = [stats(1, 5), stats(2, 7), stats(3, 20)];
+        let buckets = lifetime_histogram(&records);
+        let bucket = |min, max, count| Bucket {
+            min: Duration::from_micros(min),
+            max: Duration::from_micros(max),
+            count,
+        };
+        assert_eq!(
+            vec![bucket(4, 8, 2), bucket(8, 16, 0), bucket(16, 32, 1)],
+            buckets
+        );
+        assert_eq!(bucket(0, 2, 1), lifetime_histogram(&[stats(1, 0)])[0]);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::procfs::{self, ProcFs};
+use crate::{taskstats, AsBuf, TaskStats};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+
+/// Maximum number of ancestors to follow from a task, against loops of
+/// parents by reused process IDs
+const MAX_ANCESTORS: usize = 1024;
+
+/// Collects exit records of a process and all of its descendants.
+///
+/// Records received from `Client::listen_registered` are fed by `push` and
+/// are related each other by following `ac_ppid` of each record. Since a
+/// child usually exits before its parent, ancestors of a record which are
+/// still alive are looked up through `procfs`: records whose ancestors reach
+/// the tree are kept and the others are dropped as they arrive. A record whose
+/// ancestor has already gone from proc waits for the exit record of that
+/// ancestor, which follows shortly.
+///
+/// Limitations:
+/// * Descendants that outlive the root are re-parented (usually to init) and
+///   their records won't be found in the tree.
+/// * Threads other than the leader of the root process report the root's
+///   parent as their `ac_ppid`. They are accounted only when the parent is
+///   given through `with_parent`.
+pub struct ProcessTree {
+    root: u32,
+    parent: Option<u32>,
+    /// The root and tasks of the tree which have exited
+    members: HashSet<u32>,
+    /// Exit records of the tree by the order of arrival
+    records: BTreeMap<usize, TaskStats>,
+    /// Records waiting for the exit record of their gone ancestor, by its ID
+    pending: HashMap<u32, Vec<(usize, TaskStats)>>,
+    arrivals: usize,
+    root_exited: bool,
+    procfs: Arc<dyn ProcFs>,
+}
+
+/// The result of process tree accounting.
+pub struct TreeStats {
+    /// Exit records of every task belonging to the tree, in the order of exit
+    pub tasks: Vec<TaskStats>,
+    /// Counters of all `tasks` rolled up into a single record of the root,
+    /// with the task ID 0 to tell it apart from the root's own record
+    pub total: TaskStats,
+}
+
+/// Whether a task belongs to the tree, as far as known at its exit
+enum Membership {
+    Member,
+    Outsider,
+    /// Depends on the ancestor of the ID, which has gone without its record
+    /// having arrived yet
+    Ancestor(u32),
+}
+
+impl ProcessTree {
+    /// Create a new `ProcessTree` rooted at the process `root`.
+    pub fn new(root: u32) -> Self {
+        Self {
+            root,
+            parent: None,
+            members: vec![root].into_iter().collect(),
+            records: BTreeMap::new(),
+            pending: HashMap::new(),
+            arrivals: 0,
+            root_exited: false,
+            procfs: procfs::system(),
+        }
+    }
+
+    /// Treat every task which reports `ppid` as its parent as a thread of the root.
+    ///
+    /// This is sound only when the root is the only child of `ppid`, e.g. when
+    /// the root has been spawned by the listener itself.
+    pub fn with_parent(mut self, ppid: u32) -> Self {
+        self.parent = Some(ppid);
+        self
+    }
+
+    /// Look up ancestors of tasks through `procfs` instead of /proc.
+    pub fn with_procfs(mut self, procfs: Arc<dyn ProcFs>) -> Self {
+        self.procfs = procfs;
+        self
+    }
+
+    /// Feed an exit record.
+    ///
+    /// Per-tgid records, which carry only delay accounting and have no task ID,
+    /// are ignored as their values are already included in per-task records.
+    pub fn push(&mut self, ts: TaskStats) {
+        if ts.tid == 0 {
+            return;
+        }
+        let arrival = self.arrivals;
+        self.arrivals += 1;
+        match self.membership(&ts) {
+            Membership::Member => self.admit(arrival, ts),
+            Membership::Outsider => self.reject(ts.tid),
+            Membership::Ancestor(id) => self.pending.entry(id).or_default().push((arrival, ts)),
+        }
+    }
+
+    fn membership(&self, ts: &TaskStats) -> Membership {
+        let ppid = ts.inner().ac_ppid;
+        if ts.tid == self.root || Some(ppid) == self.parent {
+            return Membership::Member;
+        }
+        let mut pid = ppid;
+        for _ in 0..MAX_ANCESTORS {
+            if self.members.contains(&pid) {
+                return Membership::Member;
+            }
+            if pid <= 1 {
+                return Membership::Outsider;
+            }
+            match self.procfs.ppid(pid) {
+                Some(ppid) => pid = ppid,
+                None => return Membership::Ancestor(pid),
+            }
+        }
+        Membership::Outsider
+    }
+
+    /// Keep the record of a member along with records waiting for it
+    fn admit(&mut self, arrival: usize, ts: TaskStats) {
+        if ts.tid == self.root {
+            self.root_exited = true;
+        }
+        self.members.insert(ts.tid);
+        self.records.insert(arrival, ts);
+        for (arrival, ts) in self.pending.remove(&ts.tid).unwrap_or_default() {
+            self.admit(arrival, ts);
+        }
+    }
+
+    /// Drop records waiting for an outsider, which are outsiders as well
+    fn reject(&mut self, tid: u32) {
+        for (_, ts) in self.pending.remove(&tid).unwrap_or_default() {
+            self.reject(ts.tid);
+        }
+    }
+
+    /// Return true if the exit record of the root has been received.
+    pub fn is_finished(&self) -> bool {
+        self.root_exited
+    }
+
+    /// Roll up records of the tree fed so far.
+    ///
+    /// Records still waiting for their ancestors are dropped.
+    pub fn finish(self) -> TreeStats {
+        let root = self.root;
+        let tasks: Vec<_> = self.records.into_values().collect();
+        let base = tasks.iter().position(|ts| ts.tid == root);
+        let mut total = base.map(|i| tasks[i]).unwrap_or_else(|| {
+            let raw: taskstats = unsafe { mem::zeroed() };
+            TaskStats::from(raw.as_buf())
+        });
+        for (i, ts) in tasks.iter().enumerate() {
+            if Some(i) != base {
+                total.accumulate(ts);
+            }
+        }
+        total.tid = 0;
+        total.inner_mut().ac_pid = 0;
+        TreeStats { tasks, total }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::path::PathBuf;
+
+    /// Live processes by their IDs to their parents
+    struct Parents(HashMap<u32, u32>);
+
+    impl ProcFs for Parents {
+        fn read_task_file(&self, tid: u32, _name: &str) -> io::Result<Vec<u8>> {
+            match self.0.get(&tid) {
+                Some(ppid) => Ok(format!("Pid:\t{}\nPPid:\t{}\n", tid, ppid).into_bytes()),
+                None => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+
+        fn read_task_link(&self, _tid: u32, _name: &str) -> io::Result<PathBuf> {
+            Err(io::ErrorKind::NotFound.into())
+        }
+
+        fn pids(&self) -> io::Result<Vec<u32>> {
+            Ok(self.0.keys().copied().collect())
+        }
+
+        fn tids(&self, pid: u32) -> io::Result<Vec<u32>> {
+            Ok(vec![pid])
+        }
+    }
+
+    fn tree(root: u32, parents: &[(u32, u32)]) -> ProcessTree {
+        let parents = Parents(parents.iter().copied().collect());
+        ProcessTree::new(root).with_procfs(Arc::new(parents))
+    }
+
+    fn record(pid: u32, ppid: u32, utime: u64) -> TaskStats {
+        let mut raw: taskstats = unsafe { mem::zeroed() };
+        raw.ac_pid = pid;
+        raw.ac_ppid = ppid;
+        raw.ac_utime = utime;
+        raw.hiwater_rss = utime;
+        TaskStats::from(raw.as_buf())
+    }
+
+    #[test]
+    fn test_process_tree() {
+        let mut tree = tree(100, &[(200, 100), (100, 50), (50, 1), (900, 1)]);
+        tree.push(record(300, 200, 1)); // grandchild exits first
+        tree.push(record(999, 900, 2)); // unrelated
+        tree.push(record(0, 0, 4)); // per-tgid record
+        assert_eq!(1, tree.records.len());
+        tree.push(record(200, 100, 8));
+        assert!(!tree.is_finished());
+        tree.push(record(100, 50, 16));
+        assert!(tree.is_finished());
+
+        let stats = tree.finish();
+        let tids: Vec<_> = stats.tasks.iter().map(|ts| ts.tid).collect();
+        assert_eq!(vec![300, 200, 100], tids);
+        assert_eq!(0, stats.total.tid);
+        assert_eq!(0, stats.total.inner().ac_pid);
+        assert_eq!(50, stats.total.inner().ac_ppid);
+        assert_eq!(25, stats.total.cpu.utime_total.as_micros());
+        assert_eq!(16, stats.total.inner().hiwater_rss);
+    }
+
+    #[test]
+    fn test_process_tree_with_parent() {
+        let mut tree = tree(100, &[(100, 50)]).with_parent(50);
+        tree.push(record(101, 50, 1)); // non-leader thread of root
+        tree.push(record(100, 50, 2));
+
+        let stats = tree.finish();
+        assert_eq!(2, stats.tasks.len());
+        assert_eq!(3, stats.total.cpu.utime_total.as_micros());
+    }
+
+    #[test]
+    fn test_process_tree_gone_ancestor() {
+        let mut tree = tree(100, &[(100, 50)]);
+        tree.push(record(301, 201, 1)); // child of 201 which has just exited
+        tree.push(record(302, 202, 2)); // child of 202 which has just exited
+        assert!(tree.records.is_empty());
+        assert_eq!(2, tree.pending.len());
+
+        tree.push(record(202, 1, 4)); // 202 turns out unrelated
+        tree.push(record(201, 100, 8)); // 201 turns out a child of the root
+        assert!(tree.pending.is_empty());
+
+        let stats = tree.finish();
+        let tids: Vec<_> = stats.tasks.iter().map(|ts| ts.tid).collect();
+        assert_eq!(vec![301, 201], tids);
+    }
+
+    #[test]
+    fn test_process_tree_no_root() {
+        let tree = ProcessTree::new(100);
+        let stats = tree.finish();
+        assert!(stats.tasks.is_empty());
+        assert_eq!(0, stats.total.tid);
+    }
+}